@@ -0,0 +1,7 @@
+ALTER TABLE api_keys
+  DROP COLUMN min_signal_strength;
+
+ALTER TABLE sessions
+  DROP COLUMN signal_strength,
+  DROP COLUMN min_signal_strength,
+  DROP COLUMN max_signal_strength;
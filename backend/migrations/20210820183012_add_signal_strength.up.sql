@@ -0,0 +1,7 @@
+ALTER TABLE sessions
+  ADD COLUMN signal_strength INTEGER,
+  ADD COLUMN min_signal_strength INTEGER,
+  ADD COLUMN max_signal_strength INTEGER;
+
+ALTER TABLE api_keys
+  ADD COLUMN min_signal_strength INTEGER;
@@ -0,0 +1,5 @@
+ALTER TABLE user_sessions DROP COLUMN location_id;
+ALTER TABLE sessions DROP COLUMN location_id;
+ALTER TABLE api_keys DROP COLUMN location_id;
+
+DELETE FROM locations WHERE name = 'Default';
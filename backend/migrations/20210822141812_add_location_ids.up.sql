@@ -0,0 +1,14 @@
+-- Everything reported before locations existed happened in the same room
+INSERT INTO locations (name) VALUES ('Default');
+
+ALTER TABLE api_keys ADD COLUMN location_id uuid REFERENCES locations (id);
+ALTER TABLE sessions ADD COLUMN location_id uuid REFERENCES locations (id);
+ALTER TABLE user_sessions ADD COLUMN location_id uuid REFERENCES locations (id);
+
+UPDATE api_keys SET location_id = (SELECT id FROM locations WHERE name = 'Default');
+UPDATE sessions SET location_id = (SELECT id FROM locations WHERE name = 'Default');
+UPDATE user_sessions SET location_id = (SELECT id FROM locations WHERE name = 'Default');
+
+ALTER TABLE api_keys ALTER COLUMN location_id SET NOT NULL;
+ALTER TABLE sessions ALTER COLUMN location_id SET NOT NULL;
+ALTER TABLE user_sessions ALTER COLUMN location_id SET NOT NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE api_keys
+  DROP COLUMN session_timeout_minutes,
+  DROP COLUMN session_grace_minutes;
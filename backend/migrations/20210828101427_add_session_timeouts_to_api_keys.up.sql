@@ -0,0 +1,3 @@
+ALTER TABLE api_keys
+  ADD COLUMN session_timeout_minutes INTEGER CHECK (session_timeout_minutes > 0),
+  ADD COLUMN session_grace_minutes INTEGER CHECK (session_grace_minutes >= 0);
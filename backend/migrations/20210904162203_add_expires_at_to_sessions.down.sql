@@ -0,0 +1,5 @@
+UPDATE user_sessions SET end_time = expires_at;
+ALTER TABLE user_sessions DROP COLUMN expires_at;
+
+UPDATE sessions SET end_time = expires_at;
+ALTER TABLE sessions DROP COLUMN expires_at;
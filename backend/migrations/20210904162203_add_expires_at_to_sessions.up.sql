@@ -0,0 +1,15 @@
+-- end_time used to be padded by the default five minute timeout, it now holds
+-- the time the device or user was last seen
+ALTER TABLE sessions ADD COLUMN expires_at TIMESTAMPTZ;
+UPDATE sessions
+SET
+  expires_at = end_time,
+  end_time = GREATEST(start_time, end_time - interval '5 minutes');
+ALTER TABLE sessions ALTER COLUMN expires_at SET NOT NULL;
+
+ALTER TABLE user_sessions ADD COLUMN expires_at TIMESTAMPTZ;
+UPDATE user_sessions
+SET
+  expires_at = end_time,
+  end_time = GREATEST(start_time, end_time - interval '5 minutes');
+ALTER TABLE user_sessions ALTER COLUMN expires_at SET NOT NULL;
@@ -0,0 +1,7 @@
+ALTER TABLE sessions DROP CONSTRAINT sessions_no_overlap;
+ALTER TABLE user_sessions DROP CONSTRAINT user_sessions_no_overlap;
+
+DROP FUNCTION merge_overlapping_sessions();
+DROP FUNCTION merge_overlapping_user_sessions();
+
+DROP EXTENSION IF EXISTS btree_gist;
@@ -0,0 +1,131 @@
+CREATE EXTENSION IF NOT EXISTS btree_gist;
+
+-- Merges sessions of the same user in the same location whose spans overlap,
+-- keeping the earliest one. Returns the number of sessions merged away.
+CREATE OR REPLACE FUNCTION merge_overlapping_user_sessions() RETURNS INTEGER AS $$
+DECLARE
+  merged_count INTEGER;
+BEGIN
+  CREATE TEMPORARY TABLE merged_user_sessions AS
+  WITH ordered AS (
+    SELECT
+      *,
+      MAX(expires_at) OVER (
+        PARTITION BY user_id, location_id
+        ORDER BY start_time, id
+        ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING
+      ) AS previous_expires_at
+    FROM user_sessions
+  ), grouped AS (
+    SELECT
+      *,
+      COUNT(*) FILTER (
+        WHERE previous_expires_at IS NULL OR start_time >= previous_expires_at
+      ) OVER (PARTITION BY user_id, location_id ORDER BY start_time, id) AS group_number
+    FROM ordered
+  )
+  SELECT
+    (ARRAY_AGG(id ORDER BY start_time, id))[1] AS id,
+    ARRAY_AGG(id ORDER BY start_time, id) AS merged_ids,
+    MIN(start_time) AS start_time,
+    MAX(end_time) AS end_time,
+    MAX(expires_at) AS expires_at
+  FROM grouped
+  GROUP BY user_id, location_id, group_number
+  HAVING COUNT(*) > 1;
+
+  DELETE FROM user_sessions
+  USING merged_user_sessions merged
+  WHERE user_sessions.id = ANY(merged.merged_ids) AND user_sessions.id <> merged.id;
+  GET DIAGNOSTICS merged_count = ROW_COUNT;
+
+  UPDATE user_sessions
+  SET
+    start_time = merged.start_time,
+    end_time = merged.end_time,
+    expires_at = merged.expires_at
+  FROM merged_user_sessions merged
+  WHERE user_sessions.id = merged.id;
+
+  DROP TABLE merged_user_sessions;
+  RETURN merged_count;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Merges sessions of the same device in the same location whose spans
+-- overlap, keeping the earliest one. Returns the number of sessions merged
+-- away.
+CREATE OR REPLACE FUNCTION merge_overlapping_sessions() RETURNS INTEGER AS $$
+DECLARE
+  merged_count INTEGER;
+BEGIN
+  CREATE TEMPORARY TABLE merged_sessions AS
+  WITH ordered AS (
+    SELECT
+      *,
+      MAX(expires_at) OVER (
+        PARTITION BY mac_address, location_id
+        ORDER BY start_time, id
+        ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING
+      ) AS previous_expires_at
+    FROM sessions
+  ), grouped AS (
+    SELECT
+      *,
+      COUNT(*) FILTER (
+        WHERE previous_expires_at IS NULL OR start_time >= previous_expires_at
+      ) OVER (PARTITION BY mac_address, location_id ORDER BY start_time, id) AS group_number
+    FROM ordered
+  )
+  SELECT
+    (ARRAY_AGG(id ORDER BY start_time, id))[1] AS id,
+    ARRAY_AGG(id ORDER BY start_time, id) AS merged_ids,
+    MIN(start_time) AS start_time,
+    MAX(end_time) AS end_time,
+    MAX(expires_at) AS expires_at,
+    (ARRAY_AGG(signal_strength ORDER BY end_time DESC))[1] AS signal_strength,
+    MIN(min_signal_strength) AS min_signal_strength,
+    MAX(max_signal_strength) AS max_signal_strength
+  FROM grouped
+  GROUP BY mac_address, location_id, group_number
+  HAVING COUNT(*) > 1;
+
+  DELETE FROM sessions
+  USING merged_sessions merged
+  WHERE sessions.id = ANY(merged.merged_ids) AND sessions.id <> merged.id;
+  GET DIAGNOSTICS merged_count = ROW_COUNT;
+
+  UPDATE sessions
+  SET
+    start_time = merged.start_time,
+    end_time = merged.end_time,
+    expires_at = merged.expires_at,
+    signal_strength = merged.signal_strength,
+    min_signal_strength = merged.min_signal_strength,
+    max_signal_strength = merged.max_signal_strength
+  FROM merged_sessions merged
+  WHERE sessions.id = merged.id;
+
+  DROP TABLE merged_sessions;
+  RETURN merged_count;
+END;
+$$ LANGUAGE plpgsql;
+
+SELECT merge_overlapping_user_sessions();
+SELECT merge_overlapping_sessions();
+
+-- Sessions in different locations may overlap, a user can be picked up by the
+-- reporters of two neighbouring rooms at once
+ALTER TABLE user_sessions ADD CONSTRAINT user_sessions_no_overlap
+  EXCLUDE USING gist (
+    user_id WITH =,
+    location_id WITH =,
+    tstzrange(start_time, expires_at) WITH &&
+  );
+
+ALTER TABLE sessions ADD CONSTRAINT sessions_no_overlap
+  EXCLUDE USING gist (
+    mac_address WITH =,
+    location_id WITH =,
+    tstzrange(start_time, expires_at) WITH &&
+  );
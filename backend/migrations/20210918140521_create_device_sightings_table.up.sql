@@ -0,0 +1,20 @@
+-- Sightings of addresses that don't belong to a registered device. Only a
+-- keyed hash of the address is stored, and sightings are removed a few hours
+-- after they were last seen.
+CREATE TABLE device_sightings (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  address_hash CHAR(64) NOT NULL,
+  location_id uuid NOT NULL REFERENCES locations(id),
+  first_seen TIMESTAMPTZ NOT NULL,
+  last_seen TIMESTAMPTZ NOT NULL,
+  signal_strength INTEGER NOT NULL,
+  claimed_by uuid,
+  claimed_name VARCHAR(64),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (address_hash, location_id)
+);
+
+CREATE INDEX device_sightings_last_seen_idx ON device_sightings (last_seen);
+
+SELECT manage_updated_at('device_sightings');
@@ -0,0 +1,2 @@
+ALTER TABLE devices DROP COLUMN excluded_by_admin;
+ALTER TABLE devices DROP COLUMN exclude_from_presence;
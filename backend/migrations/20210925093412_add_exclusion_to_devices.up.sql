@@ -0,0 +1,3 @@
+-- Excluded devices still get sessions, but don't make their users present
+ALTER TABLE devices ADD COLUMN exclude_from_presence BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE devices ADD COLUMN excluded_by_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,9 @@
+-- Users live in Gamma, so user_id doesn't reference any table
+CREATE TABLE user_settings (
+  user_id uuid PRIMARY KEY,
+  privacy_mode INTEGER NOT NULL DEFAULT 0 CHECK (privacy_mode BETWEEN 0 AND 2),
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(), 
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+SELECT manage_updated_at('user_settings');
@@ -0,0 +1,5 @@
+ALTER TABLE user_settings
+  DROP COLUMN display_name,
+  DROP COLUMN theme,
+  DROP COLUMN notify_weekly_summary,
+  DROP COLUMN notify_device_registered;
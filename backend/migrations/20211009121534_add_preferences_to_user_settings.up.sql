@@ -0,0 +1,5 @@
+ALTER TABLE user_settings
+  ADD COLUMN display_name VARCHAR(32),
+  ADD COLUMN theme INTEGER NOT NULL DEFAULT 0 CHECK (theme BETWEEN 0 AND 2),
+  ADD COLUMN notify_weekly_summary BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN notify_device_registered BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,17 @@
+-- OpenID Connect has no way of looking up other users, so the profiles of
+-- users are kept from when they last logged in
+CREATE TABLE oidc_users (
+  id uuid PRIMARY KEY,
+  cid VARCHAR(255) NOT NULL,
+  nick VARCHAR(255) NOT NULL,
+  first_name VARCHAR(255) NOT NULL,
+  last_name VARCHAR(255) NOT NULL,
+  avatar_url TEXT NOT NULL,
+  groups TEXT[] NOT NULL DEFAULT '{}',
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(), 
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX oidc_users_cid_idx ON oidc_users (cid);
+
+SELECT manage_updated_at('oidc_users');
@@ -0,0 +1,17 @@
+-- The tokens can't be recovered from their hashes, so keys have to be
+-- recreated after reverting
+ALTER TABLE api_keys
+  ADD COLUMN token VARCHAR(128);
+
+UPDATE api_keys
+SET token = token_hash;
+
+ALTER TABLE api_keys
+  ALTER COLUMN token SET NOT NULL,
+  ADD CONSTRAINT api_keys_token_key UNIQUE (token),
+  DROP COLUMN token_hash,
+  DROP COLUMN name,
+  DROP COLUMN scopes,
+  DROP COLUMN expires_at,
+  DROP COLUMN last_used_at,
+  DROP COLUMN revoked_at;
@@ -0,0 +1,18 @@
+ALTER TABLE api_keys
+  ADD COLUMN token_hash CHAR(64),
+  ADD COLUMN name VARCHAR(64),
+  ADD COLUMN scopes INTEGER[] NOT NULL DEFAULT '{0}',
+  ADD COLUMN expires_at TIMESTAMPTZ,
+  ADD COLUMN last_used_at TIMESTAMPTZ,
+  ADD COLUMN revoked_at TIMESTAMPTZ;
+
+UPDATE api_keys
+SET
+  token_hash = encode(sha256(convert_to(token, 'UTF8')), 'hex'),
+  name = 'Key ' || left(id::text, 8);
+
+ALTER TABLE api_keys
+  ALTER COLUMN token_hash SET NOT NULL,
+  ALTER COLUMN name SET NOT NULL,
+  ADD CONSTRAINT api_keys_token_hash_key UNIQUE (token_hash),
+  DROP COLUMN token;
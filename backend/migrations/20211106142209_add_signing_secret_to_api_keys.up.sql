@@ -0,0 +1,3 @@
+-- Reports made with keys that have a signing secret have to be signed with it
+ALTER TABLE api_keys
+  ADD COLUMN signing_secret VARCHAR(64);
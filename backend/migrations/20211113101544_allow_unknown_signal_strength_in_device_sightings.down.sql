@@ -0,0 +1,5 @@
+DELETE FROM device_sightings
+WHERE signal_strength IS NULL;
+
+ALTER TABLE device_sightings
+  ALTER COLUMN signal_strength SET NOT NULL;
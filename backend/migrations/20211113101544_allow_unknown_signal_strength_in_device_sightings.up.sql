@@ -0,0 +1,3 @@
+-- Devices reported as connecting have no known signal strength
+ALTER TABLE device_sightings
+  ALTER COLUMN signal_strength DROP NOT NULL;
@@ -0,0 +1,3 @@
+-- Reports published to the topic of a key over MQTT are made with the key
+ALTER TABLE api_keys
+  ADD COLUMN mqtt_topic VARCHAR(256) UNIQUE;
@@ -0,0 +1,5 @@
+#!/bin/sh
+# Replaces the bundled vendor table with the latest IEEE MA-L registry
+set -e
+cd "$(dirname "$0")/.."
+curl -fsSL https://standards-oui.ieee.org/oui/oui.csv -o data/oui.csv
@@ -0,0 +1,31 @@
+use dotenv::dotenv;
+use log::info;
+use sqlx::PgPool;
+
+use backend::{
+  config::Config,
+  error::HubbitResult,
+  repositories::{session::SessionRepository, user_session::UserSessionRepository},
+};
+
+/// Merges sessions that overlap, e.g. ones restored from a backup taken before
+/// overlapping sessions were prevented by the database
+#[actix_web::main]
+async fn main() -> HubbitResult<()> {
+  dotenv().ok();
+  env_logger::init();
+
+  let config = Config::from_env()?;
+
+  let db_pool = PgPool::connect(&config.db_url).await?;
+
+  let user_session_repo = UserSessionRepository::new(db_pool.clone());
+  let session_repo = SessionRepository::new(db_pool);
+
+  let merged_user_sessions = user_session_repo.merge_overlapping().await?;
+  info!("Merged {} overlapping user sessions", merged_user_sessions);
+  let merged_sessions = session_repo.merge_overlapping().await?;
+  info!("Merged {} overlapping device sessions", merged_sessions);
+
+  Ok(())
+}
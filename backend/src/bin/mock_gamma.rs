@@ -0,0 +1,337 @@
+use std::{collections::HashMap, env, fs, sync::Mutex};
+
+use actix_web::{middleware, web, App, HttpRequest, HttpResponse, HttpServer};
+use actix_web_httpauth::headers::authorization::{Basic, Bearer, Scheme};
+use dotenv::dotenv;
+use log::{info, warn};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::Deserialize;
+use url::Url;
+use uuid::Uuid;
+
+use backend::{
+  error::HubbitResult,
+  models::{GammaGroup, GammaPost, GammaSuperGroup, GammaUser},
+};
+
+const ACCESS_TOKEN_VALIDITY_SECONDS: i64 = 60 * 60;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fixture {
+  client: FixtureClient,
+  api_key: String,
+  super_groups: Vec<GammaSuperGroup>,
+  users: Vec<FixtureUser>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FixtureClient {
+  client_id: String,
+  client_secret: String,
+  redirect_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FixtureUser {
+  id: Uuid,
+  cid: String,
+  nick: String,
+  first_name: String,
+  last_name: String,
+  #[serde(default)]
+  avatar_url: String,
+  #[serde(default)]
+  groups: Vec<FixtureMembership>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FixtureMembership {
+  /// Name of the super group
+  super_group: String,
+  #[serde(default = "default_active")]
+  active: bool,
+  post: Option<GammaPost>,
+}
+
+fn default_active() -> bool {
+  true
+}
+
+struct MockGamma {
+  client: FixtureClient,
+  api_key: String,
+  users: Vec<GammaUser>,
+  codes: Mutex<HashMap<String, Uuid>>,
+  access_tokens: Mutex<HashMap<String, Uuid>>,
+}
+
+impl MockGamma {
+  fn from_fixture(fixture: Fixture) -> Self {
+    let super_groups = fixture
+      .super_groups
+      .into_iter()
+      .map(|super_group| (super_group.name.clone(), super_group))
+      .collect::<HashMap<_, _>>();
+    let users = fixture
+      .users
+      .into_iter()
+      .map(|user| GammaUser {
+        id: user.id,
+        cid: user.cid,
+        nick: user.nick,
+        first_name: user.first_name,
+        last_name: user.last_name,
+        avatar_url: user.avatar_url,
+        groups: user
+          .groups
+          .into_iter()
+          .filter_map(|membership| {
+            let super_group = super_groups.get(&membership.super_group);
+            if super_group.is_none() {
+              warn!(
+                "[Mock Gamma] Unknown super group {}",
+                membership.super_group
+              );
+            }
+            super_group.map(|super_group| GammaGroup {
+              active: membership.active,
+              super_group: super_group.clone(),
+              post: membership.post,
+            })
+          })
+          .collect(),
+      })
+      .collect();
+
+    Self {
+      client: fixture.client,
+      api_key: fixture.api_key,
+      users,
+      codes: Mutex::new(HashMap::new()),
+      access_tokens: Mutex::new(HashMap::new()),
+    }
+  }
+
+  fn find_user(&self, id_or_cid: &str) -> Option<&GammaUser> {
+    self
+      .users
+      .iter()
+      .find(|user| user.cid == id_or_cid || user.id.to_string() == id_or_cid)
+  }
+}
+
+/// Serves the parts of Gamma that Hubbit uses, with users from a fixture file,
+/// so that Hubbit can be run without a Gamma instance
+#[actix_web::main]
+async fn main() -> HubbitResult<()> {
+  dotenv().ok();
+  env_logger::init();
+
+  let port = env::var("MOCK_GAMMA_PORT").unwrap_or_else(|_| "8081".to_string());
+  let fixture_file =
+    env::var("MOCK_GAMMA_FIXTURE").unwrap_or_else(|_| "data/mock_gamma.json".to_string());
+  let fixture = serde_json::from_str::<Fixture>(&fs::read_to_string(&fixture_file)?)?;
+  let mock_gamma = web::Data::new(MockGamma::from_fixture(fixture));
+  info!(
+    "[Mock Gamma] Serving {} users from {} on port {}",
+    mock_gamma.users.len(),
+    fixture_file,
+    port
+  );
+
+  Ok(
+    HttpServer::new(move || {
+      App::new()
+        .wrap(middleware::Logger::default())
+        .app_data(mock_gamma.clone())
+        .service(web::resource("/api/oauth/authorize").route(web::get().to(authorize)))
+        .service(web::resource("/api/oauth/token").route(web::post().to(token)))
+        .service(web::resource("/api/users/me").route(web::get().to(get_me)))
+        .service(web::resource("/api/users/{id}").route(web::get().to(get_user)))
+    })
+    .bind(format!("0.0.0.0:{}", port))?
+    .run()
+    .await?,
+  )
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthorizeQuery {
+  client_id: String,
+  state: Option<String>,
+  /// Logs in as this user, by id or cid, instead of showing the login page
+  user: Option<String>,
+}
+
+async fn authorize(
+  mock_gamma: web::Data<MockGamma>,
+  http_req: HttpRequest,
+  query: web::Query<AuthorizeQuery>,
+) -> HttpResponse {
+  if query.client_id != mock_gamma.client.client_id {
+    warn!("[Mock Gamma] Unknown client {}", query.client_id);
+    return HttpResponse::BadRequest().body("Unknown client");
+  }
+
+  let user = match &query.user {
+    Some(user) => match mock_gamma.find_user(user) {
+      Some(user) => user,
+      None => return HttpResponse::NotFound().body("Unknown user"),
+    },
+    None => return login_page(&mock_gamma, &http_req),
+  };
+
+  let code = random_string();
+  mock_gamma
+    .codes
+    .lock()
+    .unwrap()
+    .insert(code.clone(), user.id);
+  let mut url = match Url::parse(&mock_gamma.client.redirect_uri) {
+    Ok(url) => url,
+    Err(_) => return HttpResponse::InternalServerError().body("Invalid redirect URI"),
+  };
+  url.query_pairs_mut().append_pair("code", &code);
+  if let Some(state) = &query.state {
+    url.query_pairs_mut().append_pair("state", state);
+  }
+
+  HttpResponse::TemporaryRedirect()
+    .header("Location", url.into_string())
+    .finish()
+}
+
+// Lists the users of the fixture, each linking back to the authorize endpoint
+fn login_page(mock_gamma: &MockGamma, http_req: &HttpRequest) -> HttpResponse {
+  let links = mock_gamma
+    .users
+    .iter()
+    .map(|user| {
+      format!(
+        "<li><a href=\"/api/oauth/authorize?{}&amp;user={}\">{} ({})</a></li>",
+        escape_html(http_req.query_string()),
+        user.id,
+        escape_html(&user.nick),
+        escape_html(&user.cid)
+      )
+    })
+    .collect::<String>();
+  HttpResponse::Ok()
+    .content_type("text/html; charset=utf-8")
+    .body(format!(
+      "<!DOCTYPE html><title>Mock Gamma</title><h1>Log in as</h1><ul>{}</ul>",
+      links
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+  code: String,
+}
+
+async fn token(
+  mock_gamma: web::Data<MockGamma>,
+  http_req: HttpRequest,
+  query: web::Query<TokenQuery>,
+) -> HttpResponse {
+  let basic = match http_req.headers().get("Authorization").map(Basic::parse) {
+    Some(Ok(basic)) => basic,
+    _ => {
+      warn!("[Mock Gamma] Missing client credentials");
+      return HttpResponse::Unauthorized().finish();
+    }
+  };
+  if basic.user_id() != mock_gamma.client.client_id.as_str()
+    || basic.password().map(|password| password.as_ref())
+      != Some(mock_gamma.client.client_secret.as_str())
+  {
+    warn!("[Mock Gamma] Invalid client credentials");
+    return HttpResponse::Unauthorized().finish();
+  }
+
+  // Codes can only be used once
+  let user_id = match mock_gamma.codes.lock().unwrap().remove(&query.code) {
+    Some(user_id) => user_id,
+    None => {
+      warn!("[Mock Gamma] Invalid code");
+      return HttpResponse::BadRequest().finish();
+    }
+  };
+
+  let access_token = random_string();
+  mock_gamma
+    .access_tokens
+    .lock()
+    .unwrap()
+    .insert(access_token.clone(), user_id);
+  HttpResponse::Ok().json(serde_json::json!({
+    "access_token": access_token,
+    "token_type": "bearer",
+    "expires_in": ACCESS_TOKEN_VALIDITY_SECONDS,
+  }))
+}
+
+async fn get_me(mock_gamma: web::Data<MockGamma>, http_req: HttpRequest) -> HttpResponse {
+  let bearer = match http_req.headers().get("Authorization").map(Bearer::parse) {
+    Some(Ok(bearer)) => bearer,
+    _ => return HttpResponse::Unauthorized().finish(),
+  };
+
+  let user_id = match mock_gamma
+    .access_tokens
+    .lock()
+    .unwrap()
+    .get(bearer.token().as_ref())
+  {
+    Some(user_id) => *user_id,
+    None => return HttpResponse::Unauthorized().finish(),
+  };
+
+  match mock_gamma.find_user(&user_id.to_string()) {
+    Some(user) => HttpResponse::Ok().json(user),
+    None => HttpResponse::Unauthorized().finish(),
+  }
+}
+
+async fn get_user(
+  mock_gamma: web::Data<MockGamma>,
+  http_req: HttpRequest,
+  id: web::Path<String>,
+) -> HttpResponse {
+  let expected_header = format!("pre-shared {}", mock_gamma.api_key);
+  let is_authorized = http_req
+    .headers()
+    .get("Authorization")
+    .map_or(false, |header| {
+      header.as_bytes() == expected_header.as_bytes()
+    });
+  if !is_authorized {
+    warn!("[Mock Gamma] Invalid api key");
+    return HttpResponse::Unauthorized().finish();
+  }
+
+  match mock_gamma.find_user(&id) {
+    Some(user) => HttpResponse::Ok().json(user),
+    None => HttpResponse::NotFound().finish(),
+  }
+}
+
+fn random_string() -> String {
+  thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(32)
+    .map(char::from)
+    .collect()
+}
+
+fn escape_html(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
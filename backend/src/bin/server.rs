@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 
 use actix_session::CookieSession;
 use actix_web::{middleware, web, App, HttpServer};
@@ -12,16 +12,25 @@ use uuid::Uuid;
 
 use backend::{
   broker::SimpleBroker,
-  config::Config,
+  config::{Config, IdentityProviderKind},
   error::HubbitResult,
   event::UserEvent,
   handlers,
+  identity::{gamma::GammaProvider, oidc::OidcProvider, DynIdentityProvider},
+  models::{PrivacyMode, SessionTimeouts},
   repositories::{
-    device::DeviceRepository, session::SessionRepository, study_period::StudyPeriodRepository,
-    study_year::StudyYearRepository, user::UserRepository, user_session::UserSessionRepository,
+    api_key::ApiKeyRepository, device::DeviceRepository, device_sighting::DeviceSightingRepository,
+    location::LocationRepository, oidc_user::OidcUserRepository,
+    personal_access_token::PersonalAccessTokenRepository, session::SessionRepository,
+    study_period::StudyPeriodRepository, study_year::StudyYearRepository,
+    user_session::UserSessionRepository, user_settings::UserSettingsRepository,
   },
   schema::{HubbitSchema, MutationRoot, QueryRoot, SubscriptionRoot},
-  services::{hour_stats::HourStatsService, stats::StatsService, user::UserService},
+  services::{
+    auth::AuthService, hour_stats::HourStatsService, report_signature::ReportSignatureService,
+    stats::StatsService, user::UserService,
+  },
+  utils::oui::OuiTable,
 };
 
 #[actix_web::main]
@@ -38,12 +47,32 @@ async fn main() -> HubbitResult<()> {
   let redis_pool = Pool::builder().build(redis_manager);
 
   // Create repos
+  let api_key_repo = ApiKeyRepository::new(db_pool.clone());
   let device_repo = DeviceRepository::new(db_pool.clone());
+  let device_sighting_repo = DeviceSightingRepository::new(
+    db_pool.clone(),
+    config.sighting_secret.clone(),
+    config.sighting_retention_hours,
+  );
+  let location_repo = LocationRepository::new(db_pool.clone());
+  let personal_access_token_repo = PersonalAccessTokenRepository::new(db_pool.clone());
   let session_repo = SessionRepository::new(db_pool.clone());
   let study_period_repo = StudyPeriodRepository::new(db_pool.clone());
   let study_year_repo = StudyYearRepository::new(db_pool.clone());
-  let user_repo = UserRepository::new(config.clone());
   let user_session_repo = UserSessionRepository::new(db_pool.clone());
+  let user_settings_repo = UserSettingsRepository::new(db_pool.clone());
+
+  let identity_provider: DynIdentityProvider = match config.identity_provider {
+    IdentityProviderKind::Gamma => Arc::new(GammaProvider::new(config.clone())),
+    IdentityProviderKind::Oidc => Arc::new(
+      OidcProvider::discover(config.clone(), OidcUserRepository::new(db_pool.clone())).await?,
+    ),
+  };
+
+  let oui_table = match &config.oui_file {
+    Some(path) => OuiTable::from_file(path)?,
+    None => OuiTable::bundled(),
+  };
 
   // Create services
   let stats_service = StatsService::new(
@@ -53,35 +82,59 @@ async fn main() -> HubbitResult<()> {
     redis_pool.clone(),
   );
   let hour_stats_service = HourStatsService::new(user_session_repo.clone());
-  let user_service = UserService::new(user_repo, redis_pool.clone());
+  let report_signature_service = ReportSignatureService::new(redis_pool.clone());
+  let user_service = UserService::new(identity_provider.clone(), redis_pool.clone());
+  let auth_service = AuthService::new(
+    config.clone(),
+    identity_provider.clone(),
+    redis_pool.clone(),
+  );
 
   let schema = HubbitSchema::build(
     QueryRoot::default(),
     MutationRoot::default(),
     SubscriptionRoot,
   )
+  .data(api_key_repo)
+  .data(auth_service.clone())
+  .data(config.clone())
   .data(device_repo)
+  .data(device_sighting_repo.clone())
   .data(stats_service.clone())
   .data(hour_stats_service)
+  .data(location_repo)
+  .data(oui_table)
+  .data(personal_access_token_repo)
   .data(session_repo)
   .data(study_period_repo)
   .data(study_year_repo)
   .data(user_service.clone())
   .data(user_session_repo.clone())
+  .data(user_settings_repo.clone())
   .finish();
 
-  tokio::spawn(async move { track_sessions(user_session_repo).await });
+  let session_timeouts = config.session_timeouts();
+  tokio::spawn(async move {
+    track_sessions(user_session_repo, user_settings_repo, session_timeouts).await
+  });
+  let auth_service_clone = auth_service.clone();
+  tokio::spawn(async move { prune_tokens(auth_service_clone).await });
+  tokio::spawn(async move { prune_sightings(device_sighting_repo).await });
+  let stats_service_clone = stats_service.clone();
+  let user_service_clone = user_service.clone();
   tokio::spawn(async move {
     init_cache(stats_service, user_service)
       .await
       .map_err(|e| warn!("Failed to init cache: {:?}", e))
   });
 
+  handlers::mqtt::init(&config, db_pool.clone(), stats_service_clone.clone())?;
+
   let config_clone = config.clone();
   let port = config.port.clone();
   let cookie_secret = config.cookie_secret.clone();
   let cookie_secure = config.cookie_secure;
-  if cookie_secret.as_bytes().len() != 32 {
+  if cookie_secret.len() != 32 {
     panic!("Cookie secret must be exactly 32 bytes");
   }
 
@@ -93,6 +146,11 @@ async fn main() -> HubbitResult<()> {
         .data(config_clone.clone())
         .data(db_pool.clone())
         .data(redis_pool.clone())
+        .data(stats_service_clone.clone())
+        .data(report_signature_service.clone())
+        .data(auth_service.clone())
+        .data(user_service_clone.clone())
+        .data(identity_provider.clone())
         .data(schema.clone())
         .service(web::scope("/api").configure(handlers::init))
     })
@@ -102,9 +160,13 @@ async fn main() -> HubbitResult<()> {
   )
 }
 
-async fn track_sessions(user_session_repo: UserSessionRepository) -> HubbitResult<()> {
+async fn track_sessions(
+  user_session_repo: UserSessionRepository,
+  user_settings_repo: UserSettingsRepository,
+  timeouts: SessionTimeouts,
+) -> HubbitResult<()> {
   let mut present_users: HashSet<_> = loop {
-    match get_active_users(&user_session_repo).await {
+    match get_active_users(&user_session_repo, timeouts).await {
       Ok(present_users) => break present_users,
       _ => {
         warn!("[Session tracker] Could not get initial active users, retrying in 5 seconds...");
@@ -115,7 +177,7 @@ async fn track_sessions(user_session_repo: UserSessionRepository) -> HubbitResul
 
   loop {
     tokio::time::delay_for(std::time::Duration::from_secs(5)).await;
-    if let Ok(new_present_users) = get_active_users(&user_session_repo).await {
+    if let Ok(new_present_users) = get_active_users(&user_session_repo, timeouts).await {
       let mut new_users = Vec::new();
       let mut absent_users = Vec::new();
       for present_user in present_users.iter() {
@@ -130,14 +192,35 @@ async fn track_sessions(user_session_repo: UserSessionRepository) -> HubbitResul
         }
       }
 
+      if new_users.is_empty() && absent_users.is_empty() {
+        continue;
+      }
+
+      // Only visible users are broadcast, none if the settings can't be read
+      let hidden_user_ids = user_settings_repo
+        .get_user_ids_with_privacy_mode(PrivacyMode::Ghost)
+        .await
+        .map(|user_ids| user_ids.into_iter().collect::<HashSet<_>>())
+        .map_err(|_| error!("[Session tracker] Could not get hidden users"))
+        .ok();
+      let is_visible = |user_id| {
+        hidden_user_ids
+          .as_ref()
+          .map_or(false, |hidden_user_ids| !hidden_user_ids.contains(&user_id))
+      };
+
       for new_user in new_users {
         present_users.insert(new_user);
-        SimpleBroker::publish(UserEvent::Join(new_user));
+        if is_visible(new_user.0) {
+          SimpleBroker::publish(UserEvent::Join(new_user.0, new_user.1));
+        }
       }
 
       for absent_user in absent_users {
         present_users.remove(&absent_user);
-        SimpleBroker::publish(UserEvent::Leave(absent_user));
+        if is_visible(absent_user.0) {
+          SimpleBroker::publish(UserEvent::Leave(absent_user.0, absent_user.1));
+        }
       }
     } else {
       error!("[Session tracker] Could not get active users");
@@ -145,19 +228,42 @@ async fn track_sessions(user_session_repo: UserSessionRepository) -> HubbitResul
   }
 }
 
+// Presence is tracked per location, as well as for all locations combined
 async fn get_active_users(
   user_session_repo: &UserSessionRepository,
-) -> HubbitResult<HashSet<Uuid>> {
+  timeouts: SessionTimeouts,
+) -> HubbitResult<HashSet<(Uuid, Option<Uuid>)>> {
   Ok(
     user_session_repo
-      .get_active()
+      .get_active(None, timeouts)
       .await?
       .into_iter()
-      .map(|session| session.user_id)
+      .flat_map(|session| {
+        vec![
+          (session.user_id, Some(session.location_id)),
+          (session.user_id, None),
+        ]
+      })
       .collect(),
   )
 }
 
+async fn prune_tokens(auth_service: AuthService) {
+  loop {
+    tokio::time::delay_for(std::time::Duration::from_secs(60)).await;
+    auth_service.prune_expired().await;
+  }
+}
+
+async fn prune_sightings(device_sighting_repo: DeviceSightingRepository) {
+  loop {
+    if device_sighting_repo.delete_expired().await.is_err() {
+      warn!("[Prune sightings] Could not delete expired sightings");
+    }
+    tokio::time::delay_for(std::time::Duration::from_secs(60)).await;
+  }
+}
+
 async fn init_cache(stats_service: StatsService, user_service: UserService) -> HubbitResult<()> {
   let earliest_date = stats_service.get_earliest_date().await?;
   let now = Local::now().naive_local().date();
@@ -170,7 +276,7 @@ async fn init_cache(stats_service: StatsService, user_service: UserService) -> H
     }
 
     stats_service
-      .get_day(date.year(), date.month(), date.day())
+      .get_day(date.year(), date.month(), date.day(), None)
       .await?;
 
     date += Duration::days(1);
@@ -185,7 +291,7 @@ async fn init_cache(stats_service: StatsService, user_service: UserService) -> H
       break;
     }
 
-    stats_service.get_month(year, month).await?;
+    stats_service.get_month(year, month, None).await?;
 
     if month == 12 {
       month = 1;
@@ -197,7 +303,7 @@ async fn init_cache(stats_service: StatsService, user_service: UserService) -> H
   info!("[Init cache] checked months");
 
   // Get alltime, since it caches full years
-  let alltime_stats = stats_service.get_alltime().await?;
+  let alltime_stats = stats_service.get_alltime(None).await?;
   info!("[Init cache] checked alltime");
 
   // Check users
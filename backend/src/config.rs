@@ -1,34 +1,96 @@
 use std::{env, str::FromStr};
 
+use crate::models::SessionTimeouts;
+
 #[derive(Clone, Debug)]
 pub struct Config {
   pub port: String,
   pub db_url: String,
   pub redis_url: String,
+  pub identity_provider: IdentityProviderKind,
   pub gamma_public_url: String,
   pub gamma_internal_url: String,
   pub gamma_api_key: String,
   pub gamma_client_id: String,
   pub gamma_client_secret: String,
+  pub oidc_issuer_url: String,
+  pub oidc_client_id: String,
+  pub oidc_client_secret: String,
+  pub oidc_redirect_url: String,
+  pub oidc_scopes: String,
+  pub oidc_groups_claim: String,
   pub cookie_secret: String,
   pub cookie_secure: bool,
+  pub sighting_secret: String,
+  pub session_timeout_minutes: i32,
+  pub session_grace_minutes: i32,
+  pub connection_timeout_minutes: i32,
+  pub max_backfill_hours: i64,
+  pub sighting_retention_hours: i32,
+  pub max_devices_per_user: usize,
+  pub oui_file: Option<String>,
+  pub admin_groups: Vec<String>,
+  pub admin_posts: Vec<String>,
+  pub token_cache_seconds: i64,
+  pub login_session_hours: i64,
+  pub redirect_origins: Vec<String>,
+  pub mqtt_url: Option<String>,
+  pub mqtt_topic: String,
+  pub mqtt_client_id: String,
+  pub mqtt_username: Option<String>,
+  pub mqtt_password: Option<String>,
 }
 
 impl Config {
   pub fn from_env() -> Result<Self, ConfigError> {
+    let identity_provider = try_read_var_or("IDENTITY_PROVIDER", IdentityProviderKind::Gamma)?;
+    let uses_gamma = identity_provider == IdentityProviderKind::Gamma;
+    let uses_oidc = identity_provider == IdentityProviderKind::Oidc;
     Ok(Self {
       port: try_read_var("PORT")?,
       db_url: try_read_var("DATABASE_URL")?,
       redis_url: try_read_var("REDIS_URL")?,
-      gamma_public_url: try_read_var("GAMMA_PUBLIC_URL")?,
-      gamma_internal_url: try_read_var("GAMMA_INTERNAL_URL")?,
-      gamma_api_key: try_read_var("GAMMA_API_KEY")?,
-      gamma_client_id: try_read_var("GAMMA_CLIENT_ID")?,
-      gamma_client_secret: try_read_var("GAMMA_CLIENT_SECRET")?,
+      identity_provider,
+      gamma_public_url: try_read_var_if("GAMMA_PUBLIC_URL", uses_gamma)?,
+      gamma_internal_url: try_read_var_if("GAMMA_INTERNAL_URL", uses_gamma)?,
+      gamma_api_key: try_read_var_if("GAMMA_API_KEY", uses_gamma)?,
+      gamma_client_id: try_read_var_if("GAMMA_CLIENT_ID", uses_gamma)?,
+      gamma_client_secret: try_read_var_if("GAMMA_CLIENT_SECRET", uses_gamma)?,
+      oidc_issuer_url: try_read_var_if("OIDC_ISSUER_URL", uses_oidc)?,
+      oidc_client_id: try_read_var_if("OIDC_CLIENT_ID", uses_oidc)?,
+      oidc_client_secret: try_read_var_if("OIDC_CLIENT_SECRET", uses_oidc)?,
+      oidc_redirect_url: try_read_var_if("OIDC_REDIRECT_URL", uses_oidc)?,
+      oidc_scopes: try_read_var_or("OIDC_SCOPES", "openid profile".to_string())?,
+      oidc_groups_claim: try_read_var_or("OIDC_GROUPS_CLAIM", "groups".to_string())?,
       cookie_secret: try_read_var("COOKIE_SECRET")?,
       cookie_secure: try_read_var("COOKIE_SECURE")?,
+      sighting_secret: try_read_var("SIGHTING_SECRET")?,
+      session_timeout_minutes: try_read_var_or("SESSION_TIMEOUT_MINUTES", 5)?,
+      session_grace_minutes: try_read_var_or("SESSION_GRACE_MINUTES", 10)?,
+      connection_timeout_minutes: try_read_var_or("CONNECTION_TIMEOUT_MINUTES", 12 * 60)?,
+      max_backfill_hours: try_read_var_or("MAX_BACKFILL_HOURS", 24)?,
+      sighting_retention_hours: try_read_var_or("SIGHTING_RETENTION_HOURS", 3)?,
+      max_devices_per_user: try_read_var_or("MAX_DEVICES_PER_USER", 10)?,
+      oui_file: env::var("OUI_FILE").ok(),
+      admin_groups: try_read_list_var("ADMIN_GROUPS"),
+      admin_posts: try_read_list_var("ADMIN_POSTS"),
+      token_cache_seconds: try_read_var_or("TOKEN_CACHE_SECONDS", 300)?,
+      login_session_hours: try_read_var_or("LOGIN_SESSION_HOURS", 12)?,
+      redirect_origins: try_read_list_var("REDIRECT_ORIGINS"),
+      mqtt_url: env::var("MQTT_URL").ok(),
+      mqtt_topic: try_read_var_or("MQTT_TOPIC", "hubbit/reports/#".to_string())?,
+      mqtt_client_id: try_read_var_or("MQTT_CLIENT_ID", "hubbit".to_string())?,
+      mqtt_username: env::var("MQTT_USERNAME").ok(),
+      mqtt_password: env::var("MQTT_PASSWORD").ok(),
     })
   }
+
+  pub fn session_timeouts(&self) -> SessionTimeouts {
+    SessionTimeouts {
+      timeout_minutes: self.session_timeout_minutes,
+      grace_minutes: self.session_grace_minutes,
+    }
+  }
 }
 
 fn try_read_var<T: FromStr>(name: &str) -> Result<T, ConfigError> {
@@ -38,6 +100,55 @@ fn try_read_var<T: FromStr>(name: &str) -> Result<T, ConfigError> {
     .map_err(|_| ConfigError::InvalidVar(name.to_string()))
 }
 
+fn try_read_var_or<T: FromStr>(name: &str, default: T) -> Result<T, ConfigError> {
+  match env::var(name) {
+    Ok(value) => value
+      .parse::<T>()
+      .map_err(|_| ConfigError::InvalidVar(name.to_string())),
+    Err(_) => Ok(default),
+  }
+}
+
+// Variables that are only needed by some configurations are empty otherwise
+fn try_read_var_if(name: &str, required: bool) -> Result<String, ConfigError> {
+  if required {
+    try_read_var(name)
+  } else {
+    try_read_var_or(name, String::new())
+  }
+}
+
+// Reads a comma separated list, which is empty if the variable isn't defined
+fn try_read_list_var(name: &str) -> Vec<String> {
+  match env::var(name) {
+    Ok(value) => value
+      .split(',')
+      .map(str::trim)
+      .filter(|item| !item.is_empty())
+      .map(str::to_owned)
+      .collect(),
+    Err(_) => Vec::new(),
+  }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IdentityProviderKind {
+  Gamma,
+  Oidc,
+}
+
+impl FromStr for IdentityProviderKind {
+  type Err = ();
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "gamma" => Ok(Self::Gamma),
+      "oidc" => Ok(Self::Oidc),
+      _ => Err(()),
+    }
+  }
+}
+
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum ConfigError {
   #[error("Environment variable {0} not defined")]
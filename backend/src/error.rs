@@ -20,6 +20,24 @@ pub enum HubbitError {
   NotFound,
 }
 
+impl HubbitError {
+  /// Whether the error was caused by a row conflicting with a unique constraint
+  pub fn is_unique_violation(&self) -> bool {
+    match self {
+      HubbitError::SqlxError(sqlx::Error::Database(e)) => e.code().as_deref() == Some("23505"),
+      _ => false,
+    }
+  }
+
+  /// Whether the error was caused by a row referencing a row that doesn't exist
+  pub fn is_foreign_key_violation(&self) -> bool {
+    match self {
+      HubbitError::SqlxError(sqlx::Error::Database(e)) => e.code().as_deref() == Some("23503"),
+      _ => false,
+    }
+  }
+}
+
 pub type HubbitResult<T> = Result<T, HubbitError>;
 
 impl ResponseError for HubbitError {}
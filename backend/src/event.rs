@@ -1,7 +1,12 @@
 use uuid::Uuid;
 
+/// Presence changes, either for a single location or, if the location is
+/// `None`, for all locations combined. Joins and leaves are only published
+/// for visible users.
 #[derive(Clone)]
 pub enum UserEvent {
-  Join(Uuid),
-  Leave(Uuid),
+  Join(Uuid, Option<Uuid>),
+  Leave(Uuid, Option<Uuid>),
+  /// The user stopped being shown as present, in every location
+  Hide(Uuid),
 }
@@ -6,30 +6,34 @@ use actix_web::{
 use log::{error, warn};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use serde::{Deserialize, Serialize};
+use url::Url;
 
-use crate::config::Config;
+use crate::{
+  config::Config, identity::DynIdentityProvider, models::GammaUser, services::auth::AuthService,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct GammaInitFlowQuery {
+pub struct InitFlowQuery {
   from: Option<String>,
 }
 
-async fn gamma_init_flow(
+async fn init_flow(
   config: web::Data<Config>,
+  identity_provider: web::Data<DynIdentityProvider>,
+  auth_service: web::Data<AuthService>,
   session: Session,
-  query: web::Query<GammaInitFlowQuery>,
+  query: web::Query<InitFlowQuery>,
 ) -> HttpResponse {
-  if let Ok(Some(access_token)) = session.get::<String>("gamma_access_token") {
-    if crate::utils::gamma::get_current_user(&config, &access_token)
-      .await
-      .is_ok()
-    {
-      let url = query.from.clone().unwrap_or_else(|| "/".to_string());
-      return HttpResponse::TemporaryRedirect()
-        .header("Location", url)
-        .finish();
-    }
-  };
+  let from = query
+    .from
+    .as_deref()
+    .filter(|from| is_allowed_redirect(from, &config));
+  if get_session_user(&session, &auth_service).await.is_some() {
+    let url = from.unwrap_or("/");
+    return HttpResponse::TemporaryRedirect()
+      .header("Location", url)
+      .finish();
+  }
 
   let state: String = thread_rng()
     .sample_iter(&Alphanumeric)
@@ -39,16 +43,16 @@ async fn gamma_init_flow(
   match session.set("gamma_state", &state) {
     Ok(_) => {}
     Err(_) => {
-      error!("[Gamma auth] Could not set gamma_state key in cookie");
+      error!("[Auth] Could not set gamma_state key in cookie");
       return HttpResponse::InternalServerError().finish();
     }
   }
 
-  match &query.from {
+  match from {
     Some(from) => match session.set("gamma_from", from) {
       Ok(_) => {}
       Err(_) => {
-        error!("[Gamma auth] Could not set gamma_from key in cookie");
+        error!("[Auth] Could not set gamma_from key in cookie");
         return HttpResponse::InternalServerError().finish();
       }
     },
@@ -57,51 +61,71 @@ async fn gamma_init_flow(
     }
   }
 
-  let url = format!(
-    "{}/api/oauth/authorize?response_type=code&client_id={}&state={}",
-    config.gamma_public_url, config.gamma_client_id, state
-  );
+  let url = identity_provider.authorize_url(&state);
   HttpResponse::TemporaryRedirect()
     .header("Location", url)
     .finish()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct GammaTokenQuery {
+pub struct CallbackQuery {
   code: String,
   state: String,
 }
 
-async fn gamma_callback(
-  config: web::Data<Config>,
+async fn callback(
+  identity_provider: web::Data<DynIdentityProvider>,
+  auth_service: web::Data<AuthService>,
   session: Session,
-  query: web::Query<GammaTokenQuery>,
+  query: web::Query<CallbackQuery>,
 ) -> HttpResponse {
   let saved_state = match session.get::<String>("gamma_state") {
     Ok(Some(saved_state)) => saved_state,
     _ => {
-      warn!("[Gamma auth] Could not retrieve gamma_state");
+      warn!("[Auth] Could not retrieve gamma_state");
       return HttpResponse::InternalServerError().finish();
     }
   };
 
   if query.state != saved_state {
-    warn!("[Gamma auth] State mismatch");
+    warn!("[Auth] State mismatch");
     return HttpResponse::BadRequest().finish();
   }
 
-  let token_response = match crate::utils::gamma::oauth2_token(&config, &query.code).await {
+  let token_response = match identity_provider.exchange_code(&query.code).await {
     Ok(token_response) => token_response,
     Err(_) => {
-      error!("[Gamma auth] Could not get gamma access token");
+      error!("[Auth] Could not get access token");
       return HttpResponse::BadRequest().finish();
     }
   };
 
-  match session.set("gamma_access_token", token_response.access_token) {
+  // The session that is replaced won't be used anymore
+  if let Ok(Some(previous_session_id)) = session.get::<String>("session_id") {
+    if auth_service
+      .delete_session(&previous_session_id)
+      .await
+      .is_err()
+    {
+      warn!("[Auth] Could not delete previous session");
+    }
+  }
+
+  let session_id = match auth_service
+    .create_session(token_response.access_token, token_response.expires_in)
+    .await
+  {
+    Ok(session_id) => session_id,
+    Err(e) => {
+      error!("[Auth] Could not create session: {:?}", e);
+      return HttpResponse::InternalServerError().finish();
+    }
+  };
+
+  match session.set("session_id", session_id) {
     Ok(_) => {}
     Err(_) => {
-      error!("[Gamma auth] Could not set gamma_acess_token key in cookie");
+      error!("[Auth] Could not set session_id key in cookie");
       return HttpResponse::InternalServerError().finish();
     }
   }
@@ -113,14 +137,69 @@ async fn gamma_callback(
 
   session.remove("gamma_from");
   session.remove("gamma_state");
+  // Set by earlier versions, which kept the access token in the cookie
+  session.remove("gamma_access_token");
 
   HttpResponse::TemporaryRedirect()
     .header("Location", from)
     .finish()
 }
 
+/// Only paths on Hubbit itself, or URLs on one of the configured origins, may
+/// be redirected to after logging in, anything else is logged and ignored
+fn is_allowed_redirect(from: &str, config: &Config) -> bool {
+  // A path starting with `//`, or `/\`, is interpreted by browsers as a URL
+  // to another host
+  let is_relative_path = from.starts_with('/')
+    && !from.starts_with("//")
+    && !from.contains('\\')
+    && !from.chars().any(char::is_control);
+  if is_relative_path {
+    return true;
+  }
+
+  let is_allowed_origin = Url::parse(from)
+    .map(|url| {
+      let origin = url.origin().ascii_serialization();
+      config.redirect_origins.iter().any(|allowed_origin| {
+        Url::parse(allowed_origin)
+          .map(|allowed_origin| allowed_origin.origin().ascii_serialization() == origin)
+          .unwrap_or(false)
+      })
+    })
+    .unwrap_or(false);
+  if !is_allowed_origin {
+    warn!("[Auth] Rejected redirect to {:?}", from);
+  }
+
+  is_allowed_origin
+}
+
+async fn logout(auth_service: web::Data<AuthService>, session: Session) -> HttpResponse {
+  if let Ok(Some(session_id)) = session.get::<String>("session_id") {
+    if let Err(e) = auth_service.delete_session(&session_id).await {
+      error!("[Auth] Could not delete session: {:?}", e);
+      return HttpResponse::InternalServerError().finish();
+    }
+  }
+
+  session.remove("session_id");
+  HttpResponse::NoContent().finish()
+}
+
+/// Returns the user of the session in the cookie, if they are logged in
+pub async fn get_session_user(session: &Session, auth_service: &AuthService) -> Option<GammaUser> {
+  let session_id = session.get::<String>("session_id").ok()??;
+  auth_service.get_session_user(&session_id).await.ok()
+}
+
 pub fn init(config: &mut ServiceConfig) {
   config
-    .service(web::resource("/auth/gamma/login").route(web::get().to(gamma_init_flow)))
-    .service(web::resource("/auth/gamma/callback").route(web::get().to(gamma_callback)));
+    .service(web::resource("/auth/login").route(web::get().to(init_flow)))
+    .service(web::resource("/auth/callback").route(web::get().to(callback)))
+    // Where Gamma clients were registered to redirect to before other
+    // identity providers were supported
+    .service(web::resource("/auth/gamma/login").route(web::get().to(init_flow)))
+    .service(web::resource("/auth/gamma/callback").route(web::get().to(callback)))
+    .service(web::resource("/auth/logout").route(web::post().to(logout)));
 }
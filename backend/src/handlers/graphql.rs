@@ -4,10 +4,21 @@ use actix_web::{
   web::{self, ServiceConfig},
   Error, HttpRequest, HttpResponse, Result,
 };
+use actix_web_httpauth::headers::authorization::{Bearer, Scheme};
 use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
 use async_graphql_actix_web::{Request, Response, WSSubscription};
+use log::{error, warn};
+use sqlx::PgPool;
 
-use crate::{config::Config, schema::HubbitSchema};
+use crate::{
+  models::{ApiKey, GammaUser, PersonalAccessToken},
+  repositories::{api_key::ApiKeyRepository, personal_access_token::PersonalAccessTokenRepository},
+  schema::HubbitSchema,
+  services::{auth::AuthService, user::UserService},
+  utils::token::PERSONAL_ACCESS_TOKEN_PREFIX,
+};
+
+use super::auth::get_session_user;
 
 async fn playground() -> Result<HttpResponse, Error> {
   Ok(
@@ -21,37 +32,106 @@ async fn playground() -> Result<HttpResponse, Error> {
 
 async fn graphql(
   session: Session,
+  http_req: HttpRequest,
   gql_request: Request,
   schema: web::Data<HubbitSchema>,
-  config: web::Data<Config>,
+  auth_service: web::Data<AuthService>,
+  user_service: web::Data<UserService>,
+  pool: web::Data<PgPool>,
 ) -> Response {
   let mut request = gql_request.into_inner();
-  if let Ok(Some(access_token)) = session.get::<String>("gamma_access_token") {
-    if let Ok(user) = crate::utils::gamma::get_current_user(&config, &access_token).await {
-      request = request.data(user);
+  if let Some(user) = get_session_user(&session, &auth_service).await {
+    request = request.data(user);
+  } else if let Some(token) = get_bearer_token(&http_req) {
+    // The guards of the schema decide what each kind of token may do
+    if token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX) {
+      if let Some((personal_access_token, user)) =
+        get_personal_access_token(&token, &pool, &user_service).await
+      {
+        request = request.data(user).data(personal_access_token);
+      }
+    } else if let Some(api_key) = get_api_key(&token, &pool).await {
+      request = request.data(api_key);
     }
-  };
+  }
   schema.execute(request).await.into()
 }
 
+fn get_bearer_token(http_req: &HttpRequest) -> Option<String> {
+  let auth_header = http_req.headers().get("Authorization")?;
+  match Bearer::parse(auth_header) {
+    Ok(bearer) => Some(bearer.token().to_string()),
+    _ => {
+      warn!("[GraphQL] Invalid bearer token");
+      None
+    }
+  }
+}
+
+async fn get_api_key(token: &str, pool: &PgPool) -> Option<ApiKey> {
+  let api_key_repo = ApiKeyRepository::new(pool.clone());
+  let api_key = match api_key_repo.get_by_key(token).await {
+    Ok(api_key) => api_key,
+    Err(_) => {
+      warn!("[GraphQL] Invalid api key");
+      return None;
+    }
+  };
+
+  if api_key_repo.touch(api_key.id).await.is_err() {
+    warn!("[GraphQL] Could not record use of api key");
+  }
+
+  Some(api_key)
+}
+
+async fn get_personal_access_token(
+  token: &str,
+  pool: &PgPool,
+  user_service: &UserService,
+) -> Option<(PersonalAccessToken, GammaUser)> {
+  let personal_access_token_repo = PersonalAccessTokenRepository::new(pool.clone());
+  let personal_access_token = match personal_access_token_repo.get_by_token(token).await {
+    Ok(personal_access_token) => personal_access_token,
+    Err(_) => {
+      warn!("[GraphQL] Invalid personal access token");
+      return None;
+    }
+  };
+
+  let user = match user_service
+    .get_by_id(personal_access_token.user_id, false)
+    .await
+  {
+    Ok(user) => user,
+    Err(e) => {
+      error!(
+        "[GraphQL] Could not get user of personal access token: {:?}",
+        e
+      );
+      return None;
+    }
+  };
+
+  if personal_access_token_repo
+    .touch(personal_access_token.id)
+    .await
+    .is_err()
+  {
+    warn!("[GraphQL] Could not record use of personal access token");
+  }
+
+  Some((personal_access_token, user))
+}
+
 async fn graphql_ws(
   session: Session,
-  config: web::Data<Config>,
+  auth_service: web::Data<AuthService>,
   schema: web::Data<HubbitSchema>,
   req: HttpRequest,
   payload: web::Payload,
 ) -> Result<HttpResponse> {
-  let mut authenticated = false;
-  if let Ok(Some(access_token)) = session.get::<String>("gamma_access_token") {
-    if crate::utils::gamma::get_current_user(&config, &access_token)
-      .await
-      .is_ok()
-    {
-      authenticated = true;
-    }
-  };
-
-  if !authenticated {
+  if get_session_user(&session, &auth_service).await.is_none() {
     return Ok(HttpResponse::Unauthorized().finish());
   }
 
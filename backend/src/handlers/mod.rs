@@ -1,5 +1,6 @@
 mod auth;
 mod graphql;
+pub mod mqtt;
 mod session;
 
 use actix_web::{
@@ -0,0 +1,179 @@
+use std::{thread, time::Duration};
+
+use log::{error, info, warn};
+use rumqttc::{Client, Connection, Event, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use url::Url;
+
+use crate::{
+  config::{Config, ConfigError},
+  error::HubbitResult,
+  repositories::api_key::ApiKeyRepository,
+  services::stats::StatsService,
+};
+
+use super::session::{
+  authorize_reporter, ingest_events, ingest_reports, BatchSessionRequest, DeviceEventRequest,
+  IngestError, SessionRequest,
+};
+
+const DEFAULT_PORT: u16 = 1883;
+// Reports waiting to be recorded, further ones are dropped until they are
+const MAX_QUEUED_REPORTS: usize = 1000;
+
+/// The same bodies as those sent to `/sessions`, `/sessions/batch` and
+/// `/sessions/events`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MqttReport {
+  Events(DeviceEventRequest),
+  Batch(BatchSessionRequest),
+  Snapshot(SessionRequest),
+}
+
+/// Subscribes to the reports published to the broker in `MQTT_URL`, if it is
+/// set. MQTT doesn't tell subscribers who published a message, so reports are
+/// attributed to the API key whose MQTT topic they were published to without
+/// any further check. The broker's ACLs are the only authentication, and have
+/// to make sure that only the reporter of a key can publish to its topic.
+pub fn init(config: &Config, pool: PgPool, stats_service: StatsService) -> HubbitResult<()> {
+  let mqtt_url = match &config.mqtt_url {
+    Some(mqtt_url) => mqtt_url,
+    None => return Ok(()),
+  };
+  let url = Url::parse(mqtt_url).map_err(|_| ConfigError::InvalidVar("MQTT_URL".to_string()))?;
+  // The client is built without TLS, so `mqtts://` would silently connect in
+  // plaintext
+  if url.scheme() != "mqtt" {
+    return Err(ConfigError::InvalidVar("MQTT_URL".to_string()).into());
+  }
+  let host = url
+    .host_str()
+    .ok_or_else(|| ConfigError::InvalidVar("MQTT_URL".to_string()))?;
+
+  let mut options = MqttOptions::new(
+    config.mqtt_client_id.clone(),
+    host,
+    url.port().unwrap_or(DEFAULT_PORT),
+  );
+  options.set_keep_alive(Duration::from_secs(30));
+  if let Some(username) = &config.mqtt_username {
+    options.set_credentials(
+      username.clone(),
+      config.mqtt_password.clone().unwrap_or_default(),
+    );
+  }
+
+  // The client runs on its own thread, since it needs a newer tokio than actix
+  // does, and hands the reports over to be recorded here
+  let (client, connection) = Client::new(options, 10);
+  let (sender, receiver) = mpsc::channel(MAX_QUEUED_REPORTS);
+  let topic = config.mqtt_topic.clone();
+  thread::spawn(move || receive(client, connection, topic, sender));
+  let config = config.clone();
+  tokio::spawn(async move { record(receiver, pool, config, stats_service).await });
+
+  Ok(())
+}
+
+fn receive(
+  mut client: Client,
+  mut connection: Connection,
+  topic: String,
+  mut sender: Sender<(String, Vec<u8>)>,
+) {
+  for notification in connection.iter() {
+    match notification {
+      // Subscriptions don't outlive the connection, so they are renewed
+      // whenever the client reconnects
+      Ok(Event::Incoming(Packet::ConnAck(_))) => {
+        info!("[MQTT] Connected, subscribing to {}", topic);
+        if client
+          .try_subscribe(topic.clone(), QoS::AtLeastOnce)
+          .is_err()
+        {
+          error!("[MQTT] Could not subscribe to {}", topic);
+        }
+      }
+      Ok(Event::Incoming(Packet::Publish(publish))) => {
+        match sender.try_send((publish.topic, publish.payload.to_vec())) {
+          Ok(()) => {}
+          // Blocking here would stall the connection, so the broker would
+          // start dropping messages anyway
+          Err(TrySendError::Full((topic, _))) => {
+            warn!(
+              "[MQTT] Too many reports waiting to be recorded, dropping one published to {}",
+              topic
+            );
+          }
+          Err(TrySendError::Closed(_)) => return,
+        }
+      }
+      Ok(_) => {}
+      Err(e) => {
+        warn!(
+          "[MQTT] Connection failed, reconnecting in 5 seconds: {:?}",
+          e
+        );
+        thread::sleep(Duration::from_secs(5));
+      }
+    }
+  }
+}
+
+async fn record(
+  mut receiver: Receiver<(String, Vec<u8>)>,
+  pool: PgPool,
+  config: Config,
+  stats_service: StatsService,
+) {
+  let api_key_repo = ApiKeyRepository::new(pool.clone());
+  while let Some((topic, payload)) = receiver.recv().await {
+    let api_key = match api_key_repo.get_by_mqtt_topic(&topic).await {
+      Ok(api_key) => api_key,
+      Err(_) => {
+        warn!("[MQTT] No api key reports to {}", topic);
+        continue;
+      }
+    };
+
+    if !authorize_reporter(&api_key, &api_key_repo).await {
+      continue;
+    }
+
+    // There is nowhere to put the signature headers in an MQTT message
+    if api_key.signing_secret.is_some() {
+      warn!(
+        "[MQTT] Api key {} requires signed reports, which can't be published",
+        api_key.id
+      );
+      continue;
+    }
+
+    let report = match serde_json::from_slice::<MqttReport>(&payload) {
+      Ok(report) => report,
+      Err(_) => {
+        warn!("[MQTT] Invalid report published to {}", topic);
+        continue;
+      }
+    };
+
+    let pool = pool.clone();
+    let result = match report {
+      MqttReport::Events(event_req) => {
+        ingest_events(event_req.events, &api_key, pool, &config, &stats_service).await
+      }
+      MqttReport::Batch(batch_req) => {
+        ingest_reports(batch_req.reports, &api_key, pool, &config, &stats_service).await
+      }
+      MqttReport::Snapshot(session_req) => {
+        ingest_reports(vec![session_req], &api_key, pool, &config, &stats_service).await
+      }
+    };
+    if let Err(IngestError::Internal(e)) = result {
+      error!("[MQTT] Could not record report: {:?}", e);
+    }
+  }
+}
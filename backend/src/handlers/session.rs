@@ -1,52 +1,214 @@
+use std::{collections::HashMap, convert::TryFrom};
+
 use actix_web::{
   web::{self, ServiceConfig},
-  HttpResponse,
+  HttpRequest, HttpResponse,
 };
 use actix_web_httpauth::headers::authorization::{Bearer, Scheme};
-use log::warn;
-use serde::Deserialize;
-use sqlx::PgPool;
+use chrono::{DateTime, Duration, Local, Utc};
+use log::{error, warn};
+use serde::{de::DeserializeOwned, Deserialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
 use crate::{
-  error::HubbitResult,
+  config::Config,
+  error::{HubbitError, HubbitResult},
+  models::{ApiKey, ApiKeyScope, Device, SessionTimeouts},
   repositories::{
-    api_key::ApiKeyRepository, device::DeviceRepository, session::SessionRepository,
+    api_key::ApiKeyRepository,
+    device::{CreateDevice, DeviceRepository},
+    device_sighting::DeviceSightingRepository,
+    location::LocationRepository,
+    session::SessionRepository,
     user_session::UserSessionRepository,
   },
+  services::{
+    report_signature::{ReportSignature, ReportSignatureService, SignatureError},
+    stats::StatsService,
+  },
+  utils::mac::normalize_mac_addr,
 };
 
+// Reports timestamped further into the future than this are rejected
+const MAX_CLOCK_SKEW_MINUTES: i64 = 5;
+// Requests with more reports, or events, than this are rejected
+const MAX_BATCH_SIZE: usize = 1000;
+
+// Sent along with reports made with keys that have a signing secret
+const TIMESTAMP_HEADER: &str = "X-Hubbit-Timestamp";
+const NONCE_HEADER: &str = "X-Hubbit-Nonce";
+const SIGNATURE_HEADER: &str = "X-Hubbit-Signature";
+
 #[derive(Deserialize)]
-struct SessionRequest {
+pub(super) struct SessionRequest {
   macs: Vec<(String, u32)>,
+  /// When the addresses were observed, if not now
+  timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct BatchSessionRequest {
+  pub(super) reports: Vec<SessionRequest>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DeviceEventKind {
+  Connect,
+  Disconnect,
+}
+
+#[derive(Deserialize)]
+pub(super) struct DeviceEvent {
+  mac: String,
+  #[serde(rename = "type")]
+  kind: DeviceEventKind,
+  /// Not known to every reporter, e.g. not to those reading DHCP logs
+  signal_strength: Option<u32>,
+  /// When the event happened, if not now
+  timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct DeviceEventRequest {
+  pub(super) events: Vec<DeviceEvent>,
+}
+
+/// Why a report that was read could not be recorded
+#[derive(Debug)]
+pub(super) enum IngestError {
+  TimestampedInFuture,
+  TimestampedTooLongAgo,
+  TooManyReports,
+  Internal(HubbitError),
+}
+
+impl From<HubbitError> for IngestError {
+  fn from(e: HubbitError) -> Self {
+    IngestError::Internal(e)
+  }
+}
+
+impl From<sqlx::Error> for IngestError {
+  fn from(e: sqlx::Error) -> Self {
+    IngestError::Internal(e.into())
+  }
 }
 
 async fn update_sessions(
-  session_req: web::Json<SessionRequest>,
-  http_req: web::HttpRequest,
+  body: web::Bytes,
+  http_req: HttpRequest,
   pool: web::Data<PgPool>,
+  config: web::Data<Config>,
+  stats_service: web::Data<StatsService>,
+  report_signature_service: web::Data<ReportSignatureService>,
 ) -> HubbitResult<HttpResponse> {
   let pool = PgPool::clone(&pool);
-  let api_key_repo = ApiKeyRepository::new(pool.clone());
-  let device_repo = DeviceRepository::new(pool.clone());
-  let session_repo = SessionRepository::new(pool.clone());
-  let user_session_repo = UserSessionRepository::new(pool);
+  let (api_key, session_req) = match read_request::<SessionRequest>(
+    &http_req,
+    &body,
+    &pool,
+    &report_signature_service,
+  )
+  .await
+  {
+    Ok(request) => request,
+    Err(res) => return Ok(res),
+  };
 
-  let mut mac_addrs: Vec<String> = session_req
-    .into_inner()
-    .macs
-    .into_iter()
-    .map(|(mac, _)| mac.to_uppercase())
-    .collect();
-  mac_addrs.sort_unstable();
-  mac_addrs.dedup();
+  respond(ingest_reports(vec![session_req], &api_key, pool, &config, &stats_service).await)
+}
+
+async fn update_sessions_batch(
+  body: web::Bytes,
+  http_req: HttpRequest,
+  pool: web::Data<PgPool>,
+  config: web::Data<Config>,
+  stats_service: web::Data<StatsService>,
+  report_signature_service: web::Data<ReportSignatureService>,
+) -> HubbitResult<HttpResponse> {
+  let pool = PgPool::clone(&pool);
+  let (api_key, batch_req) =
+    match read_request::<BatchSessionRequest>(&http_req, &body, &pool, &report_signature_service)
+      .await
+    {
+      Ok(request) => request,
+      Err(res) => return Ok(res),
+    };
+
+  respond(ingest_reports(batch_req.reports, &api_key, pool, &config, &stats_service).await)
+}
+
+async fn report_events(
+  body: web::Bytes,
+  http_req: HttpRequest,
+  pool: web::Data<PgPool>,
+  config: web::Data<Config>,
+  stats_service: web::Data<StatsService>,
+  report_signature_service: web::Data<ReportSignatureService>,
+) -> HubbitResult<HttpResponse> {
+  let pool = PgPool::clone(&pool);
+  let (api_key, event_req) =
+    match read_request::<DeviceEventRequest>(&http_req, &body, &pool, &report_signature_service)
+      .await
+    {
+      Ok(request) => request,
+      Err(res) => return Ok(res),
+    };
+
+  respond(ingest_events(event_req.events, &api_key, pool, &config, &stats_service).await)
+}
+
+fn respond(result: Result<(), IngestError>) -> HubbitResult<HttpResponse> {
+  match result {
+    Ok(()) => Ok(HttpResponse::Ok().finish()),
+    Err(
+      IngestError::TimestampedInFuture
+      | IngestError::TimestampedTooLongAgo
+      | IngestError::TooManyReports,
+    ) => Ok(HttpResponse::BadRequest().finish()),
+    Err(IngestError::Internal(e)) => Err(e),
+  }
+}
+
+/// Authenticates the reporter and checks the signature of the body, if the key
+/// requires one, before the body is parsed
+async fn read_request<T: DeserializeOwned>(
+  http_req: &HttpRequest,
+  body: &[u8],
+  pool: &PgPool,
+  report_signature_service: &ReportSignatureService,
+) -> Result<(ApiKey, T), HttpResponse> {
+  let api_key = authenticate(http_req, pool).await?;
+  if let Some(signing_secret) = &api_key.signing_secret {
+    verify_signature(
+      http_req,
+      body,
+      &api_key,
+      signing_secret,
+      report_signature_service,
+    )
+    .await?;
+  }
+
+  match serde_json::from_slice::<T>(body) {
+    Ok(request) => Ok((api_key, request)),
+    Err(_) => {
+      warn!("[Update sessions] Invalid request body");
+      Err(HttpResponse::BadRequest().finish())
+    }
+  }
+}
+
+async fn authenticate(http_req: &HttpRequest, pool: &PgPool) -> Result<ApiKey, HttpResponse> {
+  let api_key_repo = ApiKeyRepository::new(pool.clone());
 
   let auth_header = match http_req.headers().get("Authorization") {
     Some(auth_header) => auth_header,
     _ => {
-      return {
-        warn!("[Update sessions] Missing authorization header");
-        Ok(HttpResponse::Unauthorized().finish())
-      }
+      warn!("[Update sessions] Missing authorization header");
+      return Err(HttpResponse::Unauthorized().finish());
     }
   };
 
@@ -54,24 +216,395 @@ async fn update_sessions(
     Ok(bearer) => bearer,
     _ => {
       warn!("[Update sessions] Invalid bearer token");
-      return Ok(HttpResponse::Unauthorized().finish());
+      return Err(HttpResponse::Unauthorized().finish());
     }
   };
-  if api_key_repo.get_by_key(bearer.token()).await.is_err() {
-    warn!("[Update sessions] Invalid api key");
-    return Ok(HttpResponse::Unauthorized().finish());
+
+  let api_key = match api_key_repo.get_by_key(bearer.token()).await {
+    Ok(api_key) => api_key,
+    Err(_) => {
+      warn!("[Update sessions] Invalid api key");
+      return Err(HttpResponse::Unauthorized().finish());
+    }
   };
 
-  let devices = device_repo.get_by_addrs(&mac_addrs).await?;
+  if !authorize_reporter(&api_key, &api_key_repo).await {
+    return Err(HttpResponse::Forbidden().finish());
+  }
+
+  Ok(api_key)
+}
+
+/// Checks that the key may report, and records that it was used if so
+pub(super) async fn authorize_reporter(api_key: &ApiKey, api_key_repo: &ApiKeyRepository) -> bool {
+  if !api_key.has_scope(ApiKeyScope::Report) {
+    warn!("[Update sessions] Api key {} may not report", api_key.id);
+    return false;
+  }
+
+  if api_key_repo.touch(api_key.id).await.is_err() {
+    warn!("[Update sessions] Could not record use of api key");
+  }
+
+  true
+}
+
+async fn verify_signature(
+  http_req: &HttpRequest,
+  body: &[u8],
+  api_key: &ApiKey,
+  signing_secret: &str,
+  report_signature_service: &ReportSignatureService,
+) -> Result<(), HttpResponse> {
+  let header = |name| {
+    http_req
+      .headers()
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+  };
+  let signature = match (
+    header(TIMESTAMP_HEADER).and_then(|timestamp| timestamp.parse().ok()),
+    header(NONCE_HEADER),
+    header(SIGNATURE_HEADER),
+  ) {
+    (Some(timestamp), Some(nonce), Some(signature)) => ReportSignature {
+      timestamp,
+      nonce: nonce.to_string(),
+      signature: signature.to_string(),
+    },
+    _ => {
+      warn!(
+        "[Signature] Missing signature headers from api key {}",
+        api_key.id
+      );
+      return Err(HttpResponse::Unauthorized().finish());
+    }
+  };
+
+  match report_signature_service
+    .verify(api_key.id, signing_secret, &signature, body)
+    .await
+  {
+    Ok(()) => Ok(()),
+    Err(SignatureError::Internal(e)) => {
+      error!("[Signature] Could not verify signature: {:?}", e);
+      Err(HttpResponse::InternalServerError().finish())
+    }
+    Err(e) => {
+      warn!(
+        "[Signature] Rejected report from api key {}: {:?}",
+        api_key.id, e
+      );
+      Err(HttpResponse::Unauthorized().finish())
+    }
+  }
+}
+
+pub(super) async fn ingest_reports(
+  reports: Vec<SessionRequest>,
+  api_key: &ApiKey,
+  pool: PgPool,
+  config: &Config,
+  stats_service: &StatsService,
+) -> Result<(), IngestError> {
+  if reports.len() > MAX_BATCH_SIZE {
+    warn!("[Update sessions] Too many reports in one request");
+    return Err(IngestError::TooManyReports);
+  }
+
+  let now = Utc::now();
+  let mut reports = reports
+    .into_iter()
+    .map(|report| {
+      let macs = report
+        .macs
+        .into_iter()
+        .map(|(mac, signal_strength)| (mac, Some(signal_strength)))
+        .collect::<Vec<_>>();
+      (report.timestamp.unwrap_or(now), macs)
+    })
+    .collect::<Vec<_>>();
+  if reports
+    .iter()
+    .any(|(seen_at, _)| *seen_at > now + Duration::minutes(MAX_CLOCK_SKEW_MINUTES))
+  {
+    warn!("[Update sessions] Report timestamped in the future");
+    return Err(IngestError::TimestampedInFuture);
+  }
+  // Closed history, and the stats cached for it, are only rewritten so far back
+  if reports
+    .iter()
+    .any(|(seen_at, _)| *seen_at < now - Duration::hours(config.max_backfill_hours))
+  {
+    warn!("[Update sessions] Report timestamped too long ago");
+    return Err(IngestError::TimestampedTooLongAgo);
+  }
+
+  // Sessions can only be continued correctly if sightings are merged in the
+  // order they happened
+  reports.sort_by_key(|(seen_at, _)| *seen_at);
+
+  // Devices are looked up before the location is locked, so that waiting
+  // requests can't hold every connection the lock holder needs
+  let device_repo = DeviceRepository::new(pool.clone());
+  let device_sighting_repo = DeviceSightingRepository::new(
+    pool.clone(),
+    config.sighting_secret.clone(),
+    config.sighting_retention_hours,
+  );
+  let mut sightings = Vec::with_capacity(reports.len());
+  for (seen_at, macs) in reports.iter() {
+    sightings.push((
+      *seen_at,
+      get_sighted_devices(macs, *seen_at, api_key, &device_repo, &device_sighting_repo).await?,
+    ));
+  }
 
+  // All reports are recorded in one transaction, holding a lock on the location
+  // so that reporters covering the same location can't race each other, and on
+  // the users so that reporters of other locations can't either
+  let location_repo = LocationRepository::new(pool.clone());
+  let session_repo = SessionRepository::new(pool.clone());
+  let user_session_repo = UserSessionRepository::new(pool.clone());
+  let mut tx = pool.begin().await?;
+  location_repo.lock(api_key.location_id, &mut tx).await?;
+  let unknown_mac_addrs = sightings
+    .iter()
+    .flat_map(|(_, devices)| devices.unknown_mac_addrs())
+    .collect::<Vec<_>>();
+  let claimed_devices = register_claimed_devices(
+    &unknown_mac_addrs,
+    config.max_devices_per_user,
+    &device_repo,
+    &device_sighting_repo,
+    &mut tx,
+  )
+  .await?;
+  let sightings = sightings
+    .into_iter()
+    .map(|(seen_at, devices)| (seen_at, devices.with_claimed(&claimed_devices)))
+    .collect::<Vec<_>>();
+  let user_ids = sightings
+    .iter()
+    .flat_map(|(_, devices)| devices.iter().map(|(device, _)| device.user_id))
+    .collect::<Vec<_>>();
+  user_session_repo.lock_users(&user_ids, &mut tx).await?;
+
+  let timeouts = api_key.session_timeouts(config.session_timeouts());
+  for (seen_at, devices) in sightings {
+    record_sightings(
+      devices,
+      api_key.location_id,
+      timeouts,
+      seen_at,
+      &session_repo,
+      &user_session_repo,
+      &mut tx,
+    )
+    .await?;
+  }
+  tx.commit().await?;
+
+  if let (Some((first_seen_at, _)), Some((last_seen_at, _))) = (reports.first(), reports.last()) {
+    invalidate_backfilled_stats(
+      *first_seen_at,
+      *last_seen_at,
+      timeouts,
+      api_key.location_id,
+      stats_service,
+    )
+    .await?;
+  }
+
+  Ok(())
+}
+
+/// Connected devices are present until they disconnect, or until the
+/// connection timeout if the disconnect is never reported. Devices can also be
+/// kept present by snapshots from `/sessions`.
+pub(super) async fn ingest_events(
+  events: Vec<DeviceEvent>,
+  api_key: &ApiKey,
+  pool: PgPool,
+  config: &Config,
+  stats_service: &StatsService,
+) -> Result<(), IngestError> {
+  if events.len() > MAX_BATCH_SIZE {
+    warn!("[Update sessions] Too many events in one request");
+    return Err(IngestError::TooManyReports);
+  }
+
+  let now = Utc::now();
+  let mut events = events
+    .into_iter()
+    .map(|event| (event.timestamp.unwrap_or(now), event))
+    .collect::<Vec<_>>();
+  if events
+    .iter()
+    .any(|(happened_at, _)| *happened_at > now + Duration::minutes(MAX_CLOCK_SKEW_MINUTES))
+  {
+    warn!("[Update sessions] Event timestamped in the future");
+    return Err(IngestError::TimestampedInFuture);
+  }
+  if events
+    .iter()
+    .any(|(happened_at, _)| *happened_at < now - Duration::hours(config.max_backfill_hours))
+  {
+    warn!("[Update sessions] Event timestamped too long ago");
+    return Err(IngestError::TimestampedTooLongAgo);
+  }
+
+  // A device that reconnects has to be disconnected first, so events that
+  // happened at the same time are kept in the order they were sent
+  events.sort_by_key(|(happened_at, _)| *happened_at);
+
+  let device_repo = DeviceRepository::new(pool.clone());
+  let device_sighting_repo = DeviceSightingRepository::new(
+    pool.clone(),
+    config.sighting_secret.clone(),
+    config.sighting_retention_hours,
+  );
+  let mut device_events = Vec::with_capacity(events.len());
+  for (happened_at, event) in events.iter() {
+    let devices = match event.kind {
+      DeviceEventKind::Connect => {
+        let macs = [(event.mac.clone(), event.signal_strength)];
+        get_sighted_devices(
+          &macs,
+          *happened_at,
+          api_key,
+          &device_repo,
+          &device_sighting_repo,
+        )
+        .await?
+      }
+      DeviceEventKind::Disconnect => match normalize_mac_addr(&event.mac) {
+        Some(mac) => device_repo
+          .get_by_addrs(&[mac])
+          .await?
+          .into_iter()
+          .map(|device| (device, None))
+          .collect::<Vec<_>>()
+          .into(),
+        None => {
+          warn!("[Update sessions] Invalid MAC address {}", event.mac);
+          SightedDevices::default()
+        }
+      },
+    };
+    device_events.push((*happened_at, event.kind, devices));
+  }
+
+  let location_repo = LocationRepository::new(pool.clone());
+  let session_repo = SessionRepository::new(pool.clone());
+  let user_session_repo = UserSessionRepository::new(pool.clone());
+  let mut tx = pool.begin().await?;
+  location_repo.lock(api_key.location_id, &mut tx).await?;
+  let unknown_mac_addrs = device_events
+    .iter()
+    .flat_map(|(_, _, devices)| devices.unknown_mac_addrs())
+    .collect::<Vec<_>>();
+  let claimed_devices = register_claimed_devices(
+    &unknown_mac_addrs,
+    config.max_devices_per_user,
+    &device_repo,
+    &device_sighting_repo,
+    &mut tx,
+  )
+  .await?;
+  let device_events = device_events
+    .into_iter()
+    .map(|(happened_at, kind, devices)| (happened_at, kind, devices.with_claimed(&claimed_devices)))
+    .collect::<Vec<_>>();
+  let user_ids = device_events
+    .iter()
+    .flat_map(|(_, _, devices)| devices.iter().map(|(device, _)| device.user_id))
+    .collect::<Vec<_>>();
+  user_session_repo.lock_users(&user_ids, &mut tx).await?;
+
+  let timeouts = api_key.session_timeouts(config.session_timeouts());
+  let connection_timeouts = SessionTimeouts {
+    timeout_minutes: config.connection_timeout_minutes,
+    ..timeouts
+  };
+  for (happened_at, kind, devices) in device_events {
+    match kind {
+      DeviceEventKind::Connect => {
+        record_sightings(
+          devices,
+          api_key.location_id,
+          connection_timeouts,
+          happened_at,
+          &session_repo,
+          &user_session_repo,
+          &mut tx,
+        )
+        .await?
+      }
+      DeviceEventKind::Disconnect => {
+        let macs = devices
+          .iter()
+          .map(|(device, _)| device.address.clone())
+          .collect::<Vec<_>>();
+        session_repo
+          .end_sessions(&macs, api_key.location_id, happened_at, &mut tx)
+          .await
+          .map_err(|e| {
+            warn!("[Update sessions] Could not end sessions");
+            e
+          })?;
+
+        let user_ids = devices
+          .iter()
+          .filter(|(device, _)| device.counts_presence())
+          .map(|(device, _)| device.user_id)
+          .collect::<Vec<_>>();
+        user_session_repo
+          .end_sessions(&user_ids, api_key.location_id, happened_at, &mut tx)
+          .await
+          .map_err(|e| {
+            warn!("[Update sessions] Could not end user sessions");
+            e
+          })?;
+      }
+    }
+  }
+  tx.commit().await?;
+
+  if let (Some((first_happened_at, _)), Some((last_happened_at, _))) =
+    (events.first(), events.last())
+  {
+    invalidate_backfilled_stats(
+      *first_happened_at,
+      *last_happened_at,
+      connection_timeouts,
+      api_key.location_id,
+      stats_service,
+    )
+    .await?;
+  }
+
+  Ok(())
+}
+
+async fn record_sightings(
+  devices: Vec<(Device, Option<i32>)>,
+  location_id: Uuid,
+  timeouts: SessionTimeouts,
+  seen_at: DateTime<Utc>,
+  session_repo: &SessionRepository,
+  user_session_repo: &UserSessionRepository,
+  tx: &mut Transaction<'_, Postgres>,
+) -> HubbitResult<()> {
   let mut user_ids = devices
     .iter()
-    .map(|device| device.user_id)
+    .filter(|(device, _)| device.counts_presence())
+    .map(|(device, _)| device.user_id)
     .collect::<Vec<_>>();
   user_ids.sort_unstable();
   user_ids.dedup();
   user_session_repo
-    .update_sessions(&user_ids)
+    .update_sessions(&user_ids, location_id, timeouts, seen_at, tx)
     .await
     .map_err(|e| {
       warn!("[Update sessions] Could not update user sessions");
@@ -80,20 +613,185 @@ async fn update_sessions(
 
   let devices = devices
     .into_iter()
-    .map(|device| (device.user_id, device.address))
+    .map(|(device, signal_strength)| (device.user_id, device.address, signal_strength))
+    .collect::<Vec<_>>();
+  session_repo
+    .update_sessions(&devices, location_id, timeouts, seen_at, tx)
+    .await
+    .map_err(|e| {
+      warn!("[Update sessions] Could not update sessions");
+      e
+    })?;
+  Ok(())
+}
+
+// Backfilled reports change days that may already have cached stats
+async fn invalidate_backfilled_stats(
+  first_seen_at: DateTime<Utc>,
+  last_seen_at: DateTime<Utc>,
+  timeouts: SessionTimeouts,
+  location_id: Uuid,
+  stats_service: &StatsService,
+) -> HubbitResult<()> {
+  let margin = Duration::minutes((timeouts.timeout_minutes + timeouts.grace_minutes).into());
+  let start_date = (first_seen_at - margin).with_timezone(&Local).date();
+  let end_date = (last_seen_at + margin).with_timezone(&Local).date();
+  if start_date < Local::now().date() {
+    stats_service
+      .invalidate_range(
+        start_date.naive_local(),
+        end_date.naive_local(),
+        location_id,
+      )
+      .await
+      .map_err(|e| {
+        warn!("[Update sessions] Could not invalidate cached stats");
+        e
+      })?;
+  }
+  Ok(())
+}
+
+/// The sighted addresses along with their signal strength, if known
+#[derive(Default)]
+struct SightedDevices {
+  registered: Vec<(Device, Option<i32>)>,
+  unknown: Vec<(String, Option<i32>)>,
+}
+
+impl SightedDevices {
+  fn unknown_mac_addrs(&self) -> impl Iterator<Item = String> + '_ {
+    self.unknown.iter().map(|(mac, _)| mac.clone())
+  }
+
+  /// Returns the sighted devices, including the unknown addresses that have
+  /// been registered since they were claimed
+  fn with_claimed(self, claimed_devices: &HashMap<String, Device>) -> Vec<(Device, Option<i32>)> {
+    let mut devices = self.registered;
+    for (mac, signal_strength) in self.unknown {
+      if let Some(device) = claimed_devices.get(&mac) {
+        devices.push((device.clone(), signal_strength));
+      }
+    }
+    devices
+  }
+}
+
+impl From<Vec<(Device, Option<i32>)>> for SightedDevices {
+  fn from(registered: Vec<(Device, Option<i32>)>) -> Self {
+    Self {
+      registered,
+      unknown: Vec::new(),
+    }
+  }
+}
+
+/// Looks up the sighted addresses, recording the unknown ones as claimable
+async fn get_sighted_devices(
+  macs: &[(String, Option<u32>)],
+  seen_at: DateTime<Utc>,
+  api_key: &ApiKey,
+  device_repo: &DeviceRepository,
+  device_sighting_repo: &DeviceSightingRepository,
+) -> HubbitResult<SightedDevices> {
+  // Keep the strongest sighting of each address, ignoring those that are too
+  // weak to be inside the room the reporter covers
+  let mut signal_strengths: HashMap<String, Option<i32>> = HashMap::new();
+  for (mac, signal_strength) in macs {
+    let signal_strength =
+      signal_strength.map(|signal_strength| i32::try_from(signal_strength).unwrap_or(i32::MAX));
+    if let (Some(signal_strength), Some(min_signal_strength)) =
+      (signal_strength, api_key.min_signal_strength)
+    {
+      if signal_strength < min_signal_strength {
+        continue;
+      }
+    }
+
+    let mac = match normalize_mac_addr(mac) {
+      Some(mac) => mac,
+      None => {
+        warn!("[Update sessions] Invalid MAC address {}", mac);
+        continue;
+      }
+    };
+    let entry = signal_strengths.entry(mac).or_insert(signal_strength);
+    *entry = (*entry).max(signal_strength);
+  }
+  let mac_addrs = signal_strengths.keys().cloned().collect::<Vec<_>>();
+
+  let devices = device_repo.get_by_addrs(&mac_addrs).await?;
+
+  let unknown_sightings = mac_addrs
+    .into_iter()
+    .filter(|mac| !devices.iter().any(|device| device.address == *mac))
+    .filter_map(|mac| {
+      let signal_strength = *signal_strengths.get(&mac)?;
+      Some((mac, signal_strength))
+    })
     .collect::<Vec<_>>();
-  session_repo.update_sessions(&devices).await.map_err(|e| {
-    warn!("[Update sessions] Could not update sessions");
-    e
-  })?;
+  device_sighting_repo
+    .record(&unknown_sightings, api_key.location_id, seen_at)
+    .await
+    .map_err(|e| {
+      warn!("[Update sessions] Could not record unknown devices");
+      e
+    })?;
 
-  Ok(HttpResponse::Ok().finish())
+  Ok(SightedDevices {
+    registered: devices
+      .into_iter()
+      .filter_map(|device| {
+        let signal_strength = *signal_strengths.get(&device.address)?;
+        Some((device, signal_strength))
+      })
+      .collect(),
+    unknown: unknown_sightings,
+  })
+}
+
+/// Registers the claimed addresses among the unknown ones. This is done while
+/// recording the sightings, so that a claim isn't lost if recording fails.
+/// Claims of users that have reached the device limit since are dropped.
+async fn register_claimed_devices(
+  mac_addrs: &[String],
+  max_devices: usize,
+  device_repo: &DeviceRepository,
+  device_sighting_repo: &DeviceSightingRepository,
+  tx: &mut Transaction<'_, Postgres>,
+) -> HubbitResult<HashMap<String, Device>> {
+  let claims = device_sighting_repo.take_claimed(mac_addrs, tx).await?;
+  let mut devices = HashMap::new();
+  for (address, user_id, name) in claims {
+    let device = device_repo
+      .create_claimed(
+        CreateDevice {
+          user_id,
+          address,
+          name,
+          exclude_from_presence: false,
+        },
+        max_devices,
+        tx,
+      )
+      .await?;
+    match device {
+      Some(device) => {
+        devices.insert(device.address.clone(), device);
+      }
+      None => warn!("[Update sessions] Could not register a claimed device"),
+    }
+  }
+  Ok(devices)
 }
 
 pub fn init(config: &mut ServiceConfig) {
-  config.service(
-    web::resource("/sessions")
-      .route(web::post().to(update_sessions))
-      .route(web::put().to(update_sessions)),
-  );
+  config
+    .service(
+      web::resource("/sessions")
+        .route(web::post().to(update_sessions))
+        .route(web::put().to(update_sessions)),
+    )
+    .service(web::resource("/sessions/batch").route(web::post().to(update_sessions_batch)))
+    .service(web::resource("/sessions/events").route(web::post().to(report_events)));
 }
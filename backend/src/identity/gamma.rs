@@ -0,0 +1,85 @@
+use async_trait::async_trait;
+use reqwest::{header::AUTHORIZATION, Client};
+
+use crate::{
+  config::Config,
+  error::{HubbitError, HubbitResult},
+  models::GammaUser,
+};
+
+use super::{IdentityProvider, TokenResponse};
+
+#[derive(Clone)]
+pub struct GammaProvider {
+  config: Config,
+}
+
+impl GammaProvider {
+  pub fn new(config: Config) -> Self {
+    Self { config }
+  }
+}
+
+#[async_trait]
+impl IdentityProvider for GammaProvider {
+  fn authorize_url(&self, state: &str) -> String {
+    format!(
+      "{}/api/oauth/authorize?response_type=code&client_id={}&state={}",
+      self.config.gamma_public_url, self.config.gamma_client_id, state
+    )
+  }
+
+  async fn exchange_code(&self, code: &str) -> HubbitResult<TokenResponse> {
+    let client = Client::new();
+    let url = format!(
+      "{}/api/oauth/token?grant_type=authorization_code&code={}",
+      self.config.gamma_internal_url, code
+    );
+    let body_str = client
+      .post(&url)
+      .basic_auth(
+        &self.config.gamma_client_id,
+        Some(&self.config.gamma_client_secret),
+      )
+      .send()
+      .await?
+      .text()
+      .await?;
+    Ok(serde_json::from_str(&body_str)?)
+  }
+
+  async fn get_current_user(&self, access_token: &str) -> HubbitResult<GammaUser> {
+    let client = Client::new();
+    let url = format!("{}/api/users/me", self.config.gamma_internal_url);
+    let body_str = client
+      .get(&url)
+      .bearer_auth(access_token)
+      .send()
+      .await?
+      .error_for_status()?
+      .text()
+      .await?;
+    Ok(serde_json::from_str(&body_str)?)
+  }
+
+  async fn get_user(&self, id_or_cid: &str) -> HubbitResult<GammaUser> {
+    let client = Client::new();
+    let res = client
+      .get(&format!(
+        "{}/api/users/{}",
+        self.config.gamma_internal_url, id_or_cid
+      ))
+      .header(
+        AUTHORIZATION,
+        format!("pre-shared {}", self.config.gamma_api_key),
+      )
+      .send()
+      .await?;
+    if res.status() == 404 {
+      return Err(HubbitError::NotFound);
+    }
+
+    let body = res.text().await?;
+    Ok(serde_json::from_str(&body)?)
+  }
+}
@@ -0,0 +1,43 @@
+pub mod gamma;
+pub mod oidc;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+  error::HubbitResult,
+  models::{GammaGroup, GammaUser},
+};
+
+/// Where users log in and where their profiles come from. Whatever the
+/// provider, users are represented by the `GammaUser` model.
+#[async_trait]
+pub trait IdentityProvider: Send + Sync {
+  /// The URL that users are sent to in order to log in
+  fn authorize_url(&self, state: &str) -> String;
+
+  /// Exchanges the code that the provider redirected back with for a token
+  async fn exchange_code(&self, code: &str) -> HubbitResult<TokenResponse>;
+
+  async fn get_current_user(&self, access_token: &str) -> HubbitResult<GammaUser>;
+
+  /// Looks up a user by either id or cid, errors with `NotFound` if the user
+  /// doesn't exist
+  async fn get_user(&self, id_or_cid: &str) -> HubbitResult<GammaUser>;
+
+  async fn get_groups(&self, user_id: Uuid) -> HubbitResult<Vec<GammaGroup>> {
+    Ok(self.get_user(&user_id.to_string()).await?.groups)
+  }
+}
+
+pub type DynIdentityProvider = Arc<dyn IdentityProvider>;
+
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+  pub access_token: String,
+  /// Seconds until the access token expires, if the provider says
+  pub expires_in: Option<i64>,
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+  config::Config,
+  error::{HubbitError, HubbitResult},
+  models::{GammaGroup, GammaSuperGroup, GammaUser},
+  repositories::oidc_user::OidcUserRepository,
+};
+
+use super::{IdentityProvider, TokenResponse};
+
+/// The parts of the provider's discovery document that are used
+#[derive(Clone, Debug, Deserialize)]
+struct ProviderMetadata {
+  authorization_endpoint: String,
+  token_endpoint: String,
+  userinfo_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserInfo {
+  sub: String,
+  preferred_username: Option<String>,
+  nickname: Option<String>,
+  name: Option<String>,
+  given_name: Option<String>,
+  family_name: Option<String>,
+  picture: Option<String>,
+  #[serde(flatten)]
+  other_claims: HashMap<String, Value>,
+}
+
+/// A standard OpenID Connect provider, which is configured through discovery
+#[derive(Clone)]
+pub struct OidcProvider {
+  config: Config,
+  metadata: ProviderMetadata,
+  oidc_user_repo: OidcUserRepository,
+}
+
+impl OidcProvider {
+  pub async fn discover(config: Config, oidc_user_repo: OidcUserRepository) -> HubbitResult<Self> {
+    let url = format!(
+      "{}/.well-known/openid-configuration",
+      config.oidc_issuer_url.trim_end_matches('/')
+    );
+    let body_str = Client::new()
+      .get(&url)
+      .send()
+      .await?
+      .error_for_status()?
+      .text()
+      .await?;
+    Ok(Self {
+      config,
+      metadata: serde_json::from_str(&body_str)?,
+      oidc_user_repo,
+    })
+  }
+
+  fn map_user(&self, user_info: UserInfo) -> GammaUser {
+    // Subjects are only unique per issuer, and are usually not UUIDs
+    let id = Uuid::parse_str(&user_info.sub).unwrap_or_else(|_| {
+      let name = format!("{}#{}", self.config.oidc_issuer_url, user_info.sub);
+      Uuid::new_v5(&Uuid::NAMESPACE_URL, name.as_bytes())
+    });
+    let cid = user_info
+      .preferred_username
+      .clone()
+      .unwrap_or_else(|| user_info.sub.clone());
+    let nick = user_info
+      .nickname
+      .clone()
+      .or_else(|| user_info.name.clone())
+      .unwrap_or_else(|| cid.clone());
+    let groups = match user_info.other_claims.get(&self.config.oidc_groups_claim) {
+      Some(Value::Array(groups)) => groups
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|name| GammaGroup {
+          active: true,
+          super_group: GammaSuperGroup {
+            id: Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()),
+            name: name.to_owned(),
+          },
+          post: None,
+        })
+        .collect(),
+      _ => Vec::new(),
+    };
+
+    GammaUser {
+      id,
+      cid,
+      nick,
+      first_name: user_info.given_name.unwrap_or_default(),
+      last_name: user_info.family_name.unwrap_or_default(),
+      avatar_url: user_info.picture.unwrap_or_default(),
+      groups,
+    }
+  }
+}
+
+#[async_trait]
+impl IdentityProvider for OidcProvider {
+  fn authorize_url(&self, state: &str) -> String {
+    match Url::parse(&self.metadata.authorization_endpoint) {
+      Ok(mut url) => {
+        url
+          .query_pairs_mut()
+          .append_pair("response_type", "code")
+          .append_pair("client_id", &self.config.oidc_client_id)
+          .append_pair("redirect_uri", &self.config.oidc_redirect_url)
+          .append_pair("scope", &self.config.oidc_scopes)
+          .append_pair("state", state);
+        url.into_string()
+      }
+      Err(_) => self.metadata.authorization_endpoint.clone(),
+    }
+  }
+
+  async fn exchange_code(&self, code: &str) -> HubbitResult<TokenResponse> {
+    let body_str = Client::new()
+      .post(&self.metadata.token_endpoint)
+      .basic_auth(
+        &self.config.oidc_client_id,
+        Some(&self.config.oidc_client_secret),
+      )
+      .form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &self.config.oidc_redirect_url),
+      ])
+      .send()
+      .await?
+      .error_for_status()?
+      .text()
+      .await?;
+    Ok(serde_json::from_str(&body_str)?)
+  }
+
+  async fn get_current_user(&self, access_token: &str) -> HubbitResult<GammaUser> {
+    let body_str = Client::new()
+      .get(&self.metadata.userinfo_endpoint)
+      .bearer_auth(access_token)
+      .send()
+      .await?
+      .error_for_status()?
+      .text()
+      .await?;
+    let user = self.map_user(serde_json::from_str(&body_str)?);
+    self.oidc_user_repo.upsert(&user).await?;
+    Ok(user)
+  }
+
+  async fn get_user(&self, id_or_cid: &str) -> HubbitResult<GammaUser> {
+    let user = match Uuid::parse_str(id_or_cid) {
+      Ok(id) => self.oidc_user_repo.get_by_id(id).await?,
+      Err(_) => self.oidc_user_repo.get_by_cid(id_or_cid).await?,
+    };
+    user.map(GammaUser::from).ok_or(HubbitError::NotFound)
+  }
+}
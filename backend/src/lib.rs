@@ -3,6 +3,7 @@ pub mod config;
 pub mod error;
 pub mod event;
 pub mod handlers;
+pub mod identity;
 pub mod models;
 pub mod repositories;
 pub mod schema;
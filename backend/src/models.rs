@@ -1,4 +1,4 @@
-use async_graphql::Enum;
+use async_graphql::{Enum, SimpleObject};
 use serde::{Deserialize, Serialize};
 use sqlx::types::chrono::{DateTime, NaiveDate, Utc};
 use uuid::Uuid;
@@ -11,6 +11,30 @@ pub struct Device {
   pub name: String,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
+  pub exclude_from_presence: bool,
+  pub excluded_by_admin: bool,
+}
+
+impl Device {
+  /// Whether sightings of the device make its user present, excluded devices
+  /// such as stationary or shared computers only get device sessions
+  pub fn counts_presence(&self) -> bool {
+    !self.exclude_from_presence && !self.excluded_by_admin
+  }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct DeviceSighting {
+  pub id: Uuid,
+  pub address_hash: String,
+  pub location_id: Uuid,
+  pub first_seen: DateTime<Utc>,
+  pub last_seen: DateTime<Utc>,
+  pub signal_strength: Option<i32>,
+  pub claimed_by: Option<Uuid>,
+  pub claimed_name: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -20,24 +44,181 @@ pub struct Session {
   pub mac_address: String,
   pub start_time: DateTime<Utc>,
   pub end_time: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+  pub signal_strength: Option<i32>,
+  pub min_signal_strength: Option<i32>,
+  pub max_signal_strength: Option<i32>,
+  pub location_id: Uuid,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
 }
 
+impl Session {
+  /// The time the device was last seen, or now if the session hasn't expired yet
+  pub fn effective_end_time(&self) -> DateTime<Utc> {
+    let now = Utc::now();
+    if self.expires_at > now {
+      now.max(self.end_time)
+    } else {
+      self.end_time
+    }
+  }
+}
+
 #[derive(Debug, sqlx::FromRow)]
 pub struct UserSession {
   pub id: Uuid,
   pub user_id: Uuid,
   pub start_time: DateTime<Utc>,
   pub end_time: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+  pub location_id: Uuid,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
 }
 
+impl UserSession {
+  /// The time the user was last seen, or now if the session hasn't expired yet
+  pub fn effective_end_time(&self) -> DateTime<Utc> {
+    let now = Utc::now();
+    if self.expires_at > now {
+      now.max(self.end_time)
+    } else {
+      self.end_time
+    }
+  }
+}
+
 #[derive(Debug, sqlx::FromRow)]
 pub struct ApiKey {
   pub id: Uuid,
-  pub token: String,
+  pub min_signal_strength: Option<i32>,
+  pub location_id: Uuid,
+  pub session_timeout_minutes: Option<i32>,
+  pub session_grace_minutes: Option<i32>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+  pub token_hash: String,
+  pub name: String,
+  pub scopes: Vec<i32>,
+  pub expires_at: Option<DateTime<Utc>>,
+  pub last_used_at: Option<DateTime<Utc>>,
+  pub revoked_at: Option<DateTime<Utc>>,
+  pub signing_secret: Option<String>,
+  pub mqtt_topic: Option<String>,
+}
+
+impl ApiKey {
+  pub fn has_scope(&self, scope: ApiKeyScope) -> bool {
+    self.scopes.contains(&scope.into())
+  }
+
+  /// The timeouts used for reports made with this key, falling back to the
+  /// configured defaults
+  pub fn session_timeouts(&self, defaults: SessionTimeouts) -> SessionTimeouts {
+    SessionTimeouts {
+      timeout_minutes: self
+        .session_timeout_minutes
+        .unwrap_or(defaults.timeout_minutes),
+      grace_minutes: self.session_grace_minutes.unwrap_or(defaults.grace_minutes),
+    }
+  }
+}
+
+/// What an API key may be used for
+#[derive(Copy, Clone, Debug, Enum, Eq, PartialEq)]
+pub enum ApiKeyScope {
+  /// Reporting sighted devices, as done by the reporters in each location
+  Report,
+  /// Reading presence and stats through the GraphQL API
+  ReadStats,
+}
+
+impl From<i32> for ApiKeyScope {
+  fn from(value: i32) -> Self {
+    match value {
+      0 => Self::Report,
+      1 => Self::ReadStats,
+      _ => panic!("ApiKeyScope integer value must be between 0 and 1"),
+    }
+  }
+}
+
+impl From<ApiKeyScope> for i32 {
+  fn from(scope: ApiKeyScope) -> Self {
+    match scope {
+      ApiKeyScope::Report => 0,
+      ApiKeyScope::ReadStats => 1,
+    }
+  }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct PersonalAccessToken {
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub name: String,
+  pub token_hash: String,
+  pub scopes: Vec<i32>,
+  pub expires_at: Option<DateTime<Utc>>,
+  pub last_used_at: Option<DateTime<Utc>>,
+  pub revoked_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl PersonalAccessToken {
+  pub fn has_scope(&self, scope: TokenScope) -> bool {
+    self.scopes.contains(&scope.into())
+  }
+}
+
+/// What a personal access token may be used for, on behalf of its user
+#[derive(Copy, Clone, Debug, Enum, Eq, PartialEq)]
+pub enum TokenScope {
+  /// Reading presence and stats
+  ReadStats,
+  /// Reading the user's own profile, settings and devices
+  ReadAccount,
+  /// Changing the user's own settings and devices
+  WriteAccount,
+}
+
+impl From<i32> for TokenScope {
+  fn from(value: i32) -> Self {
+    match value {
+      0 => Self::ReadStats,
+      1 => Self::ReadAccount,
+      2 => Self::WriteAccount,
+      _ => panic!("TokenScope integer value must be between 0 and 2"),
+    }
+  }
+}
+
+impl From<TokenScope> for i32 {
+  fn from(scope: TokenScope) -> Self {
+    match scope {
+      TokenScope::ReadStats => 0,
+      TokenScope::ReadAccount => 1,
+      TokenScope::WriteAccount => 2,
+    }
+  }
+}
+
+/// Decides for how long a device counts as present after being seen
+#[derive(Clone, Copy, Debug, SimpleObject)]
+pub struct SessionTimeouts {
+  /// Minutes after being seen that a device still counts as present
+  pub timeout_minutes: i32,
+  /// Minutes after a session has expired during which it is continued, instead
+  /// of a new session being started
+  pub grace_minutes: i32,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Location {
+  pub id: Uuid,
+  pub name: String,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
 }
@@ -97,6 +278,80 @@ impl From<Period> for i32 {
   }
 }
 
+#[derive(Debug, sqlx::FromRow)]
+pub struct UserSettings {
+  pub user_id: Uuid,
+  pub privacy_mode: i32,
+  pub display_name: Option<String>,
+  pub theme: i32,
+  pub notify_weekly_summary: bool,
+  pub notify_device_registered: bool,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+/// Who can see that a user is present
+#[derive(Copy, Clone, Debug, Enum, Eq, PartialEq)]
+pub enum PrivacyMode {
+  /// Shown as present and on leaderboards
+  Visible,
+  /// Shown on leaderboards, but never as present
+  Ghost,
+  /// Neither shown as present nor on leaderboards
+  Hidden,
+}
+
+impl From<i32> for PrivacyMode {
+  fn from(value: i32) -> Self {
+    match value {
+      0 => Self::Visible,
+      1 => Self::Ghost,
+      2 => Self::Hidden,
+      _ => panic!("Privacy mode integer value must be between 0 and 2"),
+    }
+  }
+}
+
+impl From<PrivacyMode> for i32 {
+  fn from(privacy_mode: PrivacyMode) -> Self {
+    match privacy_mode {
+      PrivacyMode::Visible => 0,
+      PrivacyMode::Ghost => 1,
+      PrivacyMode::Hidden => 2,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, Enum, Eq, PartialEq)]
+pub enum Theme {
+  /// Follow the theme of the browser or operating system
+  System,
+  Light,
+  Dark,
+}
+
+impl From<i32> for Theme {
+  fn from(value: i32) -> Self {
+    match value {
+      0 => Self::System,
+      1 => Self::Light,
+      2 => Self::Dark,
+      _ => panic!("Theme integer value must be between 0 and 2"),
+    }
+  }
+}
+
+impl From<Theme> for i32 {
+  fn from(theme: Theme) -> Self {
+    match theme {
+      Theme::System => 0,
+      Theme::Light => 1,
+      Theme::Dark => 2,
+    }
+  }
+}
+
+/// The profile of a user, regardless of which identity provider it came from
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct GammaUser {
   pub id: Uuid,
@@ -111,11 +366,52 @@ pub struct GammaUser {
   pub groups: Vec<GammaGroup>,
 }
 
+impl GammaUser {
+  /// Whether the user is an active member of any of the admin super groups,
+  /// or holds any of the admin posts, given as `super_group/post`
+  pub fn is_admin(&self, admin_groups: &[String], admin_posts: &[String]) -> bool {
+    self
+      .groups
+      .iter()
+      .filter(|group| group.active)
+      .any(|group| {
+        admin_groups.contains(&group.super_group.name)
+          || admin_posts
+            .iter()
+            .any(|admin_post| group.holds_post(admin_post))
+      })
+  }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct GammaGroup {
   pub active: bool,
   #[serde(rename = "superGroup")]
   pub super_group: GammaSuperGroup,
+  #[serde(default)]
+  pub post: Option<GammaPost>,
+}
+
+impl GammaGroup {
+  /// Whether the membership is the post, given as `super_group/post` where the
+  /// post is matched against both its Swedish and English names
+  pub fn holds_post(&self, admin_post: &str) -> bool {
+    match (admin_post.split_once('/'), &self.post) {
+      (Some((super_group, post_name)), Some(post)) => {
+        super_group == self.super_group.name
+          && (post.sv.eq_ignore_ascii_case(post_name) || post.en.eq_ignore_ascii_case(post_name))
+      }
+      _ => false,
+    }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GammaPost {
+  #[serde(default)]
+  pub sv: String,
+  #[serde(default)]
+  pub en: String,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -123,3 +419,44 @@ pub struct GammaSuperGroup {
   pub id: Uuid,
   pub name: String,
 }
+
+/// A user of an OpenID Connect provider, as of their last login
+#[derive(Debug, sqlx::FromRow)]
+pub struct OidcUser {
+  pub id: Uuid,
+  pub cid: String,
+  pub nick: String,
+  pub first_name: String,
+  pub last_name: String,
+  pub avatar_url: String,
+  pub groups: Vec<String>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl From<OidcUser> for GammaUser {
+  fn from(user: OidcUser) -> Self {
+    Self {
+      id: user.id,
+      cid: user.cid,
+      nick: user.nick,
+      first_name: user.first_name,
+      last_name: user.last_name,
+      avatar_url: user.avatar_url,
+      // Group claims are plain names, and only contain the groups that the
+      // user is currently a member of
+      groups: user
+        .groups
+        .into_iter()
+        .map(|name| GammaGroup {
+          active: true,
+          super_group: GammaSuperGroup {
+            id: Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()),
+            name,
+          },
+          post: None,
+        })
+        .collect(),
+    }
+  }
+}
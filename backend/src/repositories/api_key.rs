@@ -1,6 +1,8 @@
+use chrono::{DateTime, Utc};
 use sqlx::PgPool;
+use uuid::Uuid;
 
-use crate::{error::HubbitResult, models::ApiKey};
+use crate::{error::HubbitResult, models::ApiKey, utils::token::hash_token};
 
 #[derive(Clone, Debug)]
 pub struct ApiKeyRepository {
@@ -12,6 +14,7 @@ impl ApiKeyRepository {
     Self { pool }
   }
 
+  /// Returns the key with the token, unless it has been revoked or has expired
   pub async fn get_by_key(&self, key: &str) -> HubbitResult<ApiKey> {
     Ok(
       sqlx::query_as!(
@@ -19,12 +22,213 @@ impl ApiKeyRepository {
         "
 SELECT *
 FROM api_keys
-WHERE token = $1
+WHERE token_hash = $1
+  AND revoked_at IS NULL
+  AND (expires_at IS NULL OR expires_at > NOW())
         ",
-        key
+        hash_token(key)
       )
       .fetch_one(&self.pool)
       .await?,
     )
   }
+
+  /// Returns the key that reports published to the MQTT topic are made with,
+  /// unless it has been revoked or has expired
+  pub async fn get_by_mqtt_topic(&self, topic: &str) -> HubbitResult<ApiKey> {
+    Ok(
+      sqlx::query_as!(
+        ApiKey,
+        "
+SELECT *
+FROM api_keys
+WHERE mqtt_topic = $1
+  AND revoked_at IS NULL
+  AND (expires_at IS NULL OR expires_at > NOW())
+        ",
+        topic
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn get_for_location(&self, location_id: Uuid) -> HubbitResult<Vec<ApiKey>> {
+    Ok(
+      sqlx::query_as!(
+        ApiKey,
+        "
+SELECT *
+FROM api_keys
+WHERE location_id = $1
+        ",
+        location_id
+      )
+      .fetch_all(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn get_all(&self) -> HubbitResult<Vec<ApiKey>> {
+    Ok(
+      sqlx::query_as!(
+        ApiKey,
+        "
+SELECT *
+FROM api_keys
+ORDER BY created_at
+        "
+      )
+      .fetch_all(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn create(&self, token: &str, data: ApiKeySettings) -> HubbitResult<ApiKey> {
+    Ok(
+      sqlx::query_as!(
+        ApiKey,
+        "
+INSERT INTO api_keys (token_hash, name, scopes, expires_at, location_id, min_signal_strength, session_timeout_minutes, session_grace_minutes, mqtt_topic)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+RETURNING *
+        ",
+        hash_token(token),
+        data.name,
+        &data.scopes,
+        data.expires_at,
+        data.location_id,
+        data.min_signal_strength,
+        data.session_timeout_minutes,
+        data.session_grace_minutes,
+        data.mqtt_topic
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn update(&self, id: Uuid, data: ApiKeySettings) -> HubbitResult<ApiKey> {
+    Ok(
+      sqlx::query_as!(
+        ApiKey,
+        "
+UPDATE api_keys
+SET
+  name = $2,
+  scopes = $3,
+  expires_at = $4,
+  location_id = $5,
+  min_signal_strength = $6,
+  session_timeout_minutes = $7,
+  session_grace_minutes = $8,
+  mqtt_topic = $9
+WHERE id = $1
+RETURNING *
+        ",
+        id,
+        data.name,
+        &data.scopes,
+        data.expires_at,
+        data.location_id,
+        data.min_signal_strength,
+        data.session_timeout_minutes,
+        data.session_grace_minutes,
+        data.mqtt_topic
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Replaces the token of a key that hasn't been revoked, the previous token
+  /// stops working immediately
+  pub async fn rotate(&self, id: Uuid, token: &str) -> HubbitResult<ApiKey> {
+    Ok(
+      sqlx::query_as!(
+        ApiKey,
+        "
+UPDATE api_keys
+SET token_hash = $2
+WHERE id = $1
+  AND revoked_at IS NULL
+RETURNING *
+        ",
+        id,
+        hash_token(token)
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Keys are kept after being revoked, so that it can be seen what they were
+  /// and when they were last used
+  pub async fn revoke(&self, id: Uuid) -> HubbitResult<ApiKey> {
+    Ok(
+      sqlx::query_as!(
+        ApiKey,
+        "
+UPDATE api_keys
+SET revoked_at = COALESCE(revoked_at, NOW())
+WHERE id = $1
+RETURNING *
+        ",
+        id
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Reports made with the key have to be signed with the secret, if it has
+  /// one
+  pub async fn set_signing_secret(
+    &self,
+    id: Uuid,
+    signing_secret: Option<&str>,
+  ) -> HubbitResult<ApiKey> {
+    Ok(
+      sqlx::query_as!(
+        ApiKey,
+        "
+UPDATE api_keys
+SET signing_secret = $2
+WHERE id = $1
+RETURNING *
+        ",
+        id,
+        signing_secret
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  // Only recorded once a minute, to not write to the database on every report
+  pub async fn touch(&self, id: Uuid) -> HubbitResult<()> {
+    sqlx::query!(
+      "
+UPDATE api_keys
+SET last_used_at = NOW()
+WHERE id = $1
+  AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+      ",
+      id
+    )
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+}
+
+pub struct ApiKeySettings {
+  pub name: String,
+  pub scopes: Vec<i32>,
+  pub expires_at: Option<DateTime<Utc>>,
+  pub location_id: Uuid,
+  pub min_signal_strength: Option<i32>,
+  pub session_timeout_minutes: Option<i32>,
+  pub session_grace_minutes: Option<i32>,
+  pub mqtt_topic: Option<String>,
 }
@@ -1,4 +1,4 @@
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{error::HubbitResult, models::Device};
@@ -66,34 +66,176 @@ WHERE user_id = $1
       sqlx::query_as!(
         Device,
         "
-INSERT INTO devices (user_id, address, name)
-VALUES ($1, $2, $3)
+INSERT INTO devices (user_id, address, name, exclude_from_presence)
+VALUES ($1, $2, $3, $4)
 RETURNING *
         ",
         data.user_id,
         data.address,
-        data.name
+        data.name,
+        data.exclude_from_presence
       )
       .fetch_one(&self.pool)
       .await?,
     )
   }
 
-  pub async fn update(&self, addr: &str, data: UpdateDevice) -> HubbitResult<Device> {
+  /// Registers a claimed address, unless it has been registered since it was
+  /// claimed or the user already has `max_devices` devices
+  pub async fn create_claimed(
+    &self,
+    data: CreateDevice,
+    max_devices: usize,
+    tx: &mut Transaction<'_, Postgres>,
+  ) -> HubbitResult<Option<Device>> {
     Ok(
       sqlx::query_as!(
         Device,
         "
-UPDATE devices
-SET
-  address = $1,
-  name = $2
-WHERE address = $3
+INSERT INTO devices (user_id, address, name, exclude_from_presence)
+SELECT $1, $2, $3, $4
+WHERE (SELECT COUNT(*) FROM devices WHERE user_id = $1) < $5
+ON CONFLICT (address) DO NOTHING
 RETURNING *
         ",
+        data.user_id,
         data.address,
         data.name,
-        addr,
+        data.exclude_from_presence,
+        max_devices as i64
+      )
+      .fetch_optional(&mut *tx)
+      .await?,
+    )
+  }
+
+  /// Replaces the devices of the user, as one transaction. Devices that are
+  /// kept keep their settings unless they are given.
+  pub async fn replace_for_user(
+    &self,
+    user_id: Uuid,
+    devices: Vec<UpdateDevice>,
+  ) -> HubbitResult<Vec<Device>> {
+    let mut tx = self.pool.begin().await?;
+    let addresses = devices
+      .iter()
+      .map(|device| device.address.clone())
+      .collect::<Vec<_>>();
+    let kept_addresses = sqlx::query!(
+      "
+SELECT address
+FROM devices
+WHERE user_id = $1
+FOR UPDATE
+      ",
+      user_id
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|row| row.address)
+    .filter(|address| addresses.contains(address))
+    .collect::<Vec<_>>();
+    sqlx::query!(
+      "
+DELETE FROM devices
+WHERE user_id = $1
+  AND address <> ALL($2)
+      ",
+      user_id,
+      &addresses
+    )
+    .execute(&mut tx)
+    .await?;
+
+    for device in devices {
+      if kept_addresses.contains(&device.address) {
+        sqlx::query!(
+          "
+UPDATE devices
+SET
+  name = $1,
+  exclude_from_presence = COALESCE($3, exclude_from_presence)
+WHERE address = $2
+          ",
+          device.name,
+          device.address,
+          device.exclude_from_presence
+        )
+        .execute(&mut tx)
+        .await?;
+      } else {
+        sqlx::query!(
+          "
+INSERT INTO devices (user_id, address, name, exclude_from_presence)
+VALUES ($1, $2, $3, $4)
+          ",
+          user_id,
+          device.address,
+          device.name,
+          device.exclude_from_presence.unwrap_or(false)
+        )
+        .execute(&mut tx)
+        .await?;
+      }
+    }
+    tx.commit().await?;
+
+    self.get_for_user(user_id).await
+  }
+
+  pub async fn rename(&self, id: Uuid, name: &str) -> HubbitResult<Device> {
+    Ok(
+      sqlx::query_as!(
+        Device,
+        "
+UPDATE devices
+SET name = $1
+WHERE id = $2
+RETURNING *
+        ",
+        name,
+        id
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn set_excluded(&self, id: Uuid, exclude_from_presence: bool) -> HubbitResult<Device> {
+    Ok(
+      sqlx::query_as!(
+        Device,
+        "
+UPDATE devices
+SET exclude_from_presence = $1
+WHERE id = $2
+RETURNING *
+        ",
+        exclude_from_presence,
+        id
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn set_excluded_by_admin(
+    &self,
+    id: Uuid,
+    excluded_by_admin: bool,
+  ) -> HubbitResult<Device> {
+    Ok(
+      sqlx::query_as!(
+        Device,
+        "
+UPDATE devices
+SET excluded_by_admin = $1
+WHERE id = $2
+RETURNING *
+        ",
+        excluded_by_admin,
+        id
       )
       .fetch_one(&self.pool)
       .await?,
@@ -118,9 +260,12 @@ pub struct CreateDevice {
   pub user_id: Uuid,
   pub address: String,
   pub name: String,
+  pub exclude_from_presence: bool,
 }
 
 pub struct UpdateDevice {
   pub address: String,
   pub name: String,
+  /// Left unchanged if `None`
+  pub exclude_from_presence: Option<bool>,
 }
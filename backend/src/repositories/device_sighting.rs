@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{error::HubbitResult, models::DeviceSighting};
+
+/// Sightings of unregistered addresses, kept so that users can claim their
+/// devices. Addresses are only ever stored as a keyed hash.
+#[derive(Clone, Debug)]
+pub struct DeviceSightingRepository {
+  pool: PgPool,
+  hash_key: String,
+  retention_hours: i32,
+}
+
+impl DeviceSightingRepository {
+  pub fn new(pool: PgPool, hash_key: String, retention_hours: i32) -> Self {
+    Self {
+      pool,
+      hash_key,
+      retention_hours,
+    }
+  }
+
+  pub async fn get_claimable(
+    &self,
+    location_id: Option<Uuid>,
+  ) -> HubbitResult<Vec<DeviceSighting>> {
+    Ok(
+      sqlx::query_as!(
+        DeviceSighting,
+        "
+SELECT *
+FROM device_sightings
+WHERE claimed_by IS NULL
+  AND last_seen > NOW() - ($2::INTEGER * interval '1 hour')
+  AND ($1::uuid IS NULL OR location_id = $1)
+ORDER BY last_seen DESC
+        ",
+        location_id,
+        self.retention_hours
+      )
+      .fetch_all(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Counts the addresses the user has claimed that haven't been registered
+  /// yet
+  pub async fn count_claimed(&self, user_id: Uuid) -> HubbitResult<i64> {
+    Ok(
+      sqlx::query!(
+        r#"
+SELECT COUNT(DISTINCT address_hash) AS "count!"
+FROM device_sightings
+WHERE claimed_by = $1
+  AND last_seen > NOW() - ($2::INTEGER * interval '1 hour')
+        "#,
+        user_id,
+        self.retention_hours
+      )
+      .fetch_one(&self.pool)
+      .await?
+      .count,
+    )
+  }
+
+  /// Marks the sighting as claimed by the user, the device is registered the
+  /// next time it is seen. Returns `None` if the sighting has expired or was
+  /// already claimed.
+  pub async fn claim(
+    &self,
+    id: Uuid,
+    user_id: Uuid,
+    name: &str,
+  ) -> HubbitResult<Option<DeviceSighting>> {
+    Ok(
+      sqlx::query_as!(
+        DeviceSighting,
+        "
+UPDATE device_sightings
+SET
+  claimed_by = $2,
+  claimed_name = $3
+WHERE id = $1
+  AND claimed_by IS NULL
+  AND last_seen > NOW() - ($4::INTEGER * interval '1 hour')
+RETURNING *
+        ",
+        id,
+        user_id,
+        name,
+        self.retention_hours
+      )
+      .fetch_optional(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Records sightings of unregistered addresses, given as
+  /// `(address, signal_strength)`
+  pub async fn record(
+    &self,
+    sightings: &[(String, Option<i32>)],
+    location_id: Uuid,
+    seen_at: DateTime<Utc>,
+  ) -> HubbitResult<()> {
+    let address_hashes = sightings
+      .iter()
+      .map(|(mac, _)| self.hash_address(mac))
+      .collect::<Vec<_>>();
+    let signal_strengths = sightings
+      .iter()
+      .map(|&(_, signal_strength)| signal_strength)
+      .collect::<Vec<_>>();
+    sqlx::query!(
+      "
+INSERT INTO device_sightings (address_hash, location_id, first_seen, last_seen, signal_strength)
+SELECT data.address_hash, $3, $4, $4, data.signal_strength
+FROM UNNEST($1::CHAR(64)[], $2::INTEGER[]) as data(address_hash, signal_strength)
+ON CONFLICT (address_hash, location_id) DO UPDATE
+SET
+  first_seen = LEAST(device_sightings.first_seen, EXCLUDED.first_seen),
+  last_seen = GREATEST(device_sightings.last_seen, EXCLUDED.last_seen),
+  signal_strength = CASE
+    WHEN EXCLUDED.last_seen >= device_sightings.last_seen
+      THEN COALESCE(EXCLUDED.signal_strength, device_sightings.signal_strength)
+    ELSE COALESCE(device_sightings.signal_strength, EXCLUDED.signal_strength)
+  END
+      ",
+      &address_hashes,
+      &signal_strengths as &[Option<i32>],
+      location_id,
+      seen_at
+    )
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+
+  /// Removes the sightings of the addresses that have been claimed, returning
+  /// the claims as `(address, user_id, name)`
+  pub async fn take_claimed(
+    &self,
+    mac_addrs: &[String],
+    tx: &mut Transaction<'_, Postgres>,
+  ) -> HubbitResult<Vec<(String, Uuid, String)>> {
+    let addresses = mac_addrs
+      .iter()
+      .map(|mac| (self.hash_address(mac), mac))
+      .collect::<HashMap<_, _>>();
+    let address_hashes = addresses.keys().cloned().collect::<Vec<_>>();
+    let sightings = sqlx::query_as!(
+      DeviceSighting,
+      "
+DELETE FROM device_sightings
+WHERE address_hash IN (
+  SELECT address_hash
+  FROM device_sightings
+  WHERE address_hash = ANY($1) AND claimed_by IS NOT NULL
+)
+RETURNING *
+      ",
+      &address_hashes
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // An address may have been sighted, and claimed, in several locations
+    let mut claims = HashMap::new();
+    for sighting in sightings {
+      if let (Some(user_id), Some(name)) = (sighting.claimed_by, sighting.claimed_name) {
+        if let Some(&mac) = addresses.get(&sighting.address_hash) {
+          claims.entry(mac.to_owned()).or_insert((user_id, name));
+        }
+      }
+    }
+
+    Ok(
+      claims
+        .into_iter()
+        .map(|(mac, (user_id, name))| (mac, user_id, name))
+        .collect(),
+    )
+  }
+
+  pub async fn delete_expired(&self) -> HubbitResult<()> {
+    sqlx::query!(
+      "
+DELETE FROM device_sightings
+WHERE last_seen <= NOW() - ($1::INTEGER * interval '1 hour')
+      ",
+      self.retention_hours
+    )
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+
+  fn hash_address(&self, mac_addr: &str) -> String {
+    let mut hmac = Hmac::<Sha256>::new_varkey(self.hash_key.as_bytes())
+      .expect("HMAC can take a key of any size");
+    hmac.update(mac_addr.as_bytes());
+    hex::encode(hmac.finalize().into_bytes())
+  }
+}
@@ -0,0 +1,63 @@
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{error::HubbitResult, models::Location};
+
+#[derive(Clone, Debug)]
+pub struct LocationRepository {
+  pool: PgPool,
+}
+
+impl LocationRepository {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+
+  pub async fn get_by_id(&self, id: Uuid) -> HubbitResult<Location> {
+    Ok(
+      sqlx::query_as!(
+        Location,
+        "
+SELECT *
+FROM locations
+WHERE id = $1
+        ",
+        id
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn get_all(&self) -> HubbitResult<Vec<Location>> {
+    Ok(
+      sqlx::query_as!(
+        Location,
+        "
+SELECT *
+FROM locations
+ORDER BY name
+        "
+      )
+      .fetch_all(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Locks the location until the transaction ends, so that sightings from
+  /// several reporters in the same location are recorded one at a time
+  pub async fn lock(&self, id: Uuid, tx: &mut Transaction<'_, Postgres>) -> HubbitResult<()> {
+    sqlx::query!(
+      "
+SELECT id
+FROM locations
+WHERE id = $1
+FOR NO KEY UPDATE
+      ",
+      id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    Ok(())
+  }
+}
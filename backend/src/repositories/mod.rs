@@ -1,7 +1,12 @@
 pub mod api_key;
 pub mod device;
+pub mod device_sighting;
+pub mod location;
+pub mod oidc_user;
+pub mod personal_access_token;
 pub mod session;
 pub mod study_period;
 pub mod study_year;
-pub mod user;
 pub mod user_session;
+pub mod user_settings;
+mod util;
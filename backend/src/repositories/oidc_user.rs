@@ -0,0 +1,90 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  error::HubbitResult,
+  models::{GammaUser, OidcUser},
+};
+
+#[derive(Clone, Debug)]
+pub struct OidcUserRepository {
+  pool: PgPool,
+}
+
+impl OidcUserRepository {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+
+  pub async fn get_by_id(&self, id: Uuid) -> HubbitResult<Option<OidcUser>> {
+    Ok(
+      sqlx::query_as!(
+        OidcUser,
+        "
+SELECT *
+FROM oidc_users
+WHERE id = $1
+        ",
+        id
+      )
+      .fetch_optional(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Usernames may be reused, in which case the user that logged in most
+  /// recently is returned
+  pub async fn get_by_cid(&self, cid: &str) -> HubbitResult<Option<OidcUser>> {
+    Ok(
+      sqlx::query_as!(
+        OidcUser,
+        "
+SELECT *
+FROM oidc_users
+WHERE cid = $1
+ORDER BY updated_at DESC
+LIMIT 1
+        ",
+        cid
+      )
+      .fetch_optional(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn upsert(&self, user: &GammaUser) -> HubbitResult<OidcUser> {
+    let groups = user
+      .groups
+      .iter()
+      .filter(|group| group.active)
+      .map(|group| group.super_group.name.clone())
+      .collect::<Vec<_>>();
+    Ok(
+      sqlx::query_as!(
+        OidcUser,
+        "
+INSERT INTO oidc_users (id, cid, nick, first_name, last_name, avatar_url, groups)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+ON CONFLICT (id) DO UPDATE
+SET
+  cid = EXCLUDED.cid,
+  nick = EXCLUDED.nick,
+  first_name = EXCLUDED.first_name,
+  last_name = EXCLUDED.last_name,
+  avatar_url = EXCLUDED.avatar_url,
+  groups = EXCLUDED.groups
+RETURNING *
+        ",
+        user.id,
+        user.cid,
+        user.nick,
+        user.first_name,
+        user.last_name,
+        user.avatar_url,
+        &groups
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+}
@@ -0,0 +1,119 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::HubbitResult, models::PersonalAccessToken, utils::token::hash_token};
+
+#[derive(Clone, Debug)]
+pub struct PersonalAccessTokenRepository {
+  pool: PgPool,
+}
+
+impl PersonalAccessTokenRepository {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+
+  /// Returns the token, unless it has been revoked or has expired
+  pub async fn get_by_token(&self, token: &str) -> HubbitResult<PersonalAccessToken> {
+    Ok(
+      sqlx::query_as!(
+        PersonalAccessToken,
+        "
+SELECT *
+FROM personal_access_tokens
+WHERE token_hash = $1
+  AND revoked_at IS NULL
+  AND (expires_at IS NULL OR expires_at > NOW())
+        ",
+        hash_token(token)
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn get_for_user(&self, user_id: Uuid) -> HubbitResult<Vec<PersonalAccessToken>> {
+    Ok(
+      sqlx::query_as!(
+        PersonalAccessToken,
+        "
+SELECT *
+FROM personal_access_tokens
+WHERE user_id = $1
+ORDER BY created_at
+        ",
+        user_id
+      )
+      .fetch_all(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn create(
+    &self,
+    user_id: Uuid,
+    token: &str,
+    data: CreatePersonalAccessToken,
+  ) -> HubbitResult<PersonalAccessToken> {
+    Ok(
+      sqlx::query_as!(
+        PersonalAccessToken,
+        "
+INSERT INTO personal_access_tokens (user_id, token_hash, name, scopes, expires_at)
+VALUES ($1, $2, $3, $4, $5)
+RETURNING *
+        ",
+        user_id,
+        hash_token(token),
+        data.name,
+        &data.scopes,
+        data.expires_at
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Only revokes tokens of the user
+  pub async fn revoke(&self, id: Uuid, user_id: Uuid) -> HubbitResult<PersonalAccessToken> {
+    Ok(
+      sqlx::query_as!(
+        PersonalAccessToken,
+        "
+UPDATE personal_access_tokens
+SET revoked_at = COALESCE(revoked_at, NOW())
+WHERE id = $1
+  AND user_id = $2
+RETURNING *
+        ",
+        id,
+        user_id
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  // Only recorded once a minute, to not write to the database on every request
+  pub async fn touch(&self, id: Uuid) -> HubbitResult<()> {
+    sqlx::query!(
+      "
+UPDATE personal_access_tokens
+SET last_used_at = NOW()
+WHERE id = $1
+  AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+      ",
+      id
+    )
+    .execute(&self.pool)
+    .await?;
+    Ok(())
+  }
+}
+
+pub struct CreatePersonalAccessToken {
+  pub name: String,
+  pub scopes: Vec<i32>,
+  pub expires_at: Option<DateTime<Utc>>,
+}
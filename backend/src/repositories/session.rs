@@ -1,7 +1,13 @@
-use sqlx::PgPool;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::{error::HubbitResult, models::Session};
+use crate::{
+  error::HubbitResult,
+  models::{Session, SessionTimeouts},
+};
+
+use super::util::merge_sighting;
 
 #[derive(Clone, Debug)]
 pub struct SessionRepository {
@@ -13,16 +19,32 @@ impl SessionRepository {
     Self { pool }
   }
 
-  pub async fn is_device_active(&self, mac_addr: String) -> HubbitResult<bool> {
+  /// Sessions are active during the grace period of their location, which is
+  /// the most lenient one of its API keys, or the default if it has none
+  pub async fn is_device_active(
+    &self,
+    mac_addr: String,
+    user_id: Uuid,
+    defaults: SessionTimeouts,
+  ) -> HubbitResult<bool> {
     match sqlx::query_as!(
       Session,
       "
-SELECT *
+SELECT sessions.*
 FROM sessions
-WHERE mac_address = $1 AND end_time + (10 * interval '1 minute') > NOW()
+LEFT JOIN (
+  SELECT location_id, MAX(COALESCE(session_grace_minutes, $2)) AS grace_minutes
+  FROM api_keys
+  GROUP BY location_id
+) AS location_timeouts ON location_timeouts.location_id = sessions.location_id
+WHERE mac_address = $1
+  AND user_id = $3
+  AND expires_at + (COALESCE(location_timeouts.grace_minutes, $2::INTEGER) * interval '1 minute') > NOW()
 LIMIT 1
       ",
-      mac_addr
+      mac_addr,
+      defaults.grace_minutes,
+      user_id
     )
     .fetch_one(&self.pool)
     .await
@@ -33,53 +55,319 @@ LIMIT 1
     }
   }
 
-  pub async fn update_sessions(&self, devices: &[(Uuid, String)]) -> HubbitResult<()> {
+  /// The latest session of the device while the user owned the address
+  pub async fn get_latest_for_device(
+    &self,
+    mac_addr: String,
+    user_id: Uuid,
+  ) -> HubbitResult<Option<Session>> {
+    Ok(
+      sqlx::query_as!(
+        Session,
+        "
+SELECT *
+FROM sessions
+WHERE mac_address = $1
+  AND user_id = $2
+ORDER BY end_time DESC
+LIMIT 1
+        ",
+        mac_addr,
+        user_id
+      )
+      .fetch_optional(&self.pool)
+      .await?,
+    )
+  }
+
+  /// The sessions of the device while the user owned the address, the latest
+  /// `limit` ones if given
+  pub async fn get_for_device(
+    &self,
+    mac_addr: String,
+    user_id: Uuid,
+    limit: Option<i64>,
+  ) -> HubbitResult<Vec<Session>> {
+    Ok(
+      sqlx::query_as!(
+        Session,
+        "
+SELECT *
+FROM sessions
+WHERE mac_address = $1
+  AND user_id = $2
+ORDER BY start_time DESC
+LIMIT $3
+        ",
+        mac_addr,
+        user_id,
+        limit
+      )
+      .fetch_all(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Records that the devices were seen in a location at `seen_at`, which may
+  /// be in the past for reports that were buffered by the reporter. The signal
+  /// strength of a device isn't known when it was reported as connecting.
+  /// Concurrent updates for the same location must be serialized by the
+  /// caller, see `LocationRepository::lock`.
+  pub async fn update_sessions(
+    &self,
+    devices: &[(Uuid, String, Option<i32>)],
+    location_id: Uuid,
+    timeouts: SessionTimeouts,
+    seen_at: DateTime<Utc>,
+    tx: &mut Transaction<'_, Postgres>,
+  ) -> HubbitResult<()> {
+    let expires_at = seen_at + Duration::minutes(timeouts.timeout_minutes.into());
     let macs = devices
       .iter()
-      .map(|(_, mac)| mac.to_owned())
+      .map(|(_, mac, _)| mac.to_owned())
       .collect::<Vec<_>>();
-    let active_sessions: Vec<Session> = sqlx::query_as!(
+    // Every session that the new sighting would overlap is merged into it, so
+    // a new session never overlaps an existing one
+    let nearby_sessions: Vec<Session> = sqlx::query_as!(
       Session,
       "
-UPDATE sessions
-SET end_time = NOW() + (5 * interval '1 minute')
-WHERE mac_address = ANY($1) AND end_time + (10 * interval '1 minute') > NOW()
-RETURNING *
+SELECT *
+FROM sessions
+WHERE mac_address = ANY($1)
+  AND location_id = $2
+  AND start_time - ($5::INTEGER * interval '1 minute') <= $4
+  AND expires_at + ($5::INTEGER * interval '1 minute') >= $3
       ",
-      macs.as_slice()
+      &macs,
+      location_id,
+      seen_at,
+      expires_at,
+      timeouts.grace_minutes
     )
-    .fetch_all(&self.pool)
+    .fetch_all(&mut *tx)
     .await?;
 
-    let inactive_devices = devices
+    let mut merged_sessions = Vec::new();
+    let mut inactive_devices = Vec::new();
+    for (user_id, mac, signal_strength) in devices {
+      let sessions = nearby_sessions
+        .iter()
+        .filter(|session| session.mac_address == *mac)
+        .collect::<Vec<_>>();
+      let spans = sessions
+        .iter()
+        .map(|session| {
+          (
+            session.id,
+            session.start_time,
+            session.end_time,
+            session.expires_at,
+          )
+        })
+        .collect::<Vec<_>>();
+      let merged_session = match merge_sighting(&spans, seen_at, expires_at) {
+        Some(merged_session) => merged_session,
+        None => {
+          inactive_devices.push((*user_id, mac.to_owned(), *signal_strength));
+          continue;
+        }
+      };
+
+      // Older sightings from a backfill must not replace the latest signal strength
+      let latest = sessions.iter().max_by_key(|session| session.end_time);
+      let latest_signal_strength = match latest {
+        Some(latest) if latest.end_time > seen_at => latest.signal_strength,
+        _ => signal_strength.or_else(|| latest.and_then(|latest| latest.signal_strength)),
+      };
+      let min_signal_strength = sessions
+        .iter()
+        .filter_map(|session| session.min_signal_strength)
+        .chain(*signal_strength)
+        .min();
+      let max_signal_strength = sessions
+        .iter()
+        .filter_map(|session| session.max_signal_strength)
+        .chain(*signal_strength)
+        .max();
+      merged_sessions.push((
+        merged_session,
+        latest_signal_strength,
+        min_signal_strength,
+        max_signal_strength,
+      ));
+    }
+
+    let merged_ids = merged_sessions
+      .iter()
+      .flat_map(|(session, _, _, _)| session.merged_ids.iter().copied())
+      .collect::<Vec<_>>();
+    sqlx::query!(
+      "
+DELETE FROM sessions
+WHERE id = ANY($1)
+      ",
+      &merged_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let ids = merged_sessions
+      .iter()
+      .map(|(session, _, _, _)| session.id)
+      .collect::<Vec<_>>();
+    let start_times = merged_sessions
+      .iter()
+      .map(|(session, _, _, _)| session.start_time)
+      .collect::<Vec<_>>();
+    let end_times = merged_sessions
+      .iter()
+      .map(|(session, _, _, _)| session.end_time)
+      .collect::<Vec<_>>();
+    let expires_ats = merged_sessions
       .iter()
-      .filter(|&(user_id, _)| {
-        !active_sessions
-          .iter()
-          .any(|active_sess| active_sess.user_id == *user_id)
-      })
-      .map(|user| user.to_owned())
+      .map(|(session, _, _, _)| session.expires_at)
       .collect::<Vec<_>>();
+    let signal_strengths = merged_sessions
+      .iter()
+      .map(|&(_, signal_strength, _, _)| signal_strength)
+      .collect::<Vec<_>>();
+    let min_signal_strengths = merged_sessions
+      .iter()
+      .map(|&(_, _, min_signal_strength, _)| min_signal_strength)
+      .collect::<Vec<_>>();
+    let max_signal_strengths = merged_sessions
+      .iter()
+      .map(|&(_, _, _, max_signal_strength)| max_signal_strength)
+      .collect::<Vec<_>>();
+    sqlx::query!(
+      "
+UPDATE sessions
+SET
+  start_time = data.start_time,
+  end_time = data.end_time,
+  expires_at = data.expires_at,
+  signal_strength = data.signal_strength,
+  min_signal_strength = data.min_signal_strength,
+  max_signal_strength = data.max_signal_strength
+FROM UNNEST(
+  $1::uuid[],
+  $2::TIMESTAMPTZ[],
+  $3::TIMESTAMPTZ[],
+  $4::TIMESTAMPTZ[],
+  $5::INTEGER[],
+  $6::INTEGER[],
+  $7::INTEGER[]
+) as data(
+  id,
+  start_time,
+  end_time,
+  expires_at,
+  signal_strength,
+  min_signal_strength,
+  max_signal_strength
+)
+WHERE sessions.id = data.id
+      ",
+      &ids,
+      &start_times,
+      &end_times,
+      &expires_ats,
+      &signal_strengths as &[Option<i32>],
+      &min_signal_strengths as &[Option<i32>],
+      &max_signal_strengths as &[Option<i32>]
+    )
+    .execute(&mut *tx)
+    .await?;
+
     let inactive_user_ids = inactive_devices
       .iter()
-      .map(|(user_id, _)| user_id.to_owned())
+      .map(|(user_id, _, _)| user_id.to_owned())
       .collect::<Vec<_>>();
     let inactive_macs = inactive_devices
       .iter()
-      .map(|(_, mac)| mac.to_owned())
+      .map(|(_, mac, _)| mac.to_owned())
+      .collect::<Vec<_>>();
+    let inactive_signal_strengths = inactive_devices
+      .iter()
+      .map(|&(_, _, signal_strength)| signal_strength)
       .collect::<Vec<_>>();
-
     sqlx::query!(
       "
-INSERT INTO sessions (user_id, mac_address, start_time, end_time)
-SELECT data.user_id, data.mac_address, NOW(), NOW() + (5 * interval '1 minute')
-FROM UNNEST($1::uuid[], $2::CHAR(17)[]) as data(user_id, mac_address)
+INSERT INTO sessions (
+  user_id,
+  mac_address,
+  start_time,
+  end_time,
+  expires_at,
+  signal_strength,
+  min_signal_strength,
+  max_signal_strength,
+  location_id
+)
+SELECT
+  data.user_id,
+  data.mac_address,
+  $5,
+  $5,
+  $6,
+  data.signal_strength,
+  data.signal_strength,
+  data.signal_strength,
+  $4
+FROM UNNEST($1::uuid[], $2::CHAR(17)[], $3::INTEGER[]) as data(user_id, mac_address, signal_strength)
       ",
       &inactive_user_ids,
-      &inactive_macs
+      &inactive_macs,
+      &inactive_signal_strengths as &[Option<i32>],
+      location_id,
+      seen_at,
+      expires_at
     )
-    .fetch_all(&self.pool)
+    .execute(&mut *tx)
     .await?;
     Ok(())
   }
+
+  /// Ends the sessions of the devices in a location at `ended_at`, if they
+  /// were ongoing then and haven't been continued by later sightings
+  pub async fn end_sessions(
+    &self,
+    macs: &[String],
+    location_id: Uuid,
+    ended_at: DateTime<Utc>,
+    tx: &mut Transaction<'_, Postgres>,
+  ) -> HubbitResult<()> {
+    sqlx::query!(
+      "
+UPDATE sessions
+SET
+  end_time = $3,
+  expires_at = $3
+WHERE mac_address = ANY($1)
+  AND location_id = $2
+  AND start_time <= $3
+  AND end_time <= $3
+  AND expires_at >= $3
+      ",
+      macs,
+      location_id,
+      ended_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+  }
+
+  /// Merges overlapping sessions of a device in a location, returning the
+  /// number of sessions that were merged away
+  pub async fn merge_overlapping(&self) -> HubbitResult<i32> {
+    let merged = sqlx::query!(
+      "
+SELECT merge_overlapping_sessions() AS \"merged_count!\"
+      "
+    )
+    .fetch_one(&self.pool)
+    .await?;
+    Ok(merged.merged_count)
+  }
 }
@@ -1,4 +1,6 @@
-use sqlx::PgPool;
+use chrono::NaiveDate;
+use sqlx::{Done, PgPool};
+use uuid::Uuid;
 
 use crate::{
   error::HubbitResult,
@@ -51,4 +53,86 @@ WHERE start_date < NOW() AND end_date > NOW()
 
     Ok(study_period)
   }
+
+  pub async fn get_all(&self) -> HubbitResult<Vec<StudyPeriod>> {
+    Ok(
+      sqlx::query_as!(
+        StudyPeriod,
+        "
+SELECT *
+FROM study_periods
+ORDER BY start_date DESC
+        "
+      )
+      .fetch_all(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn create(&self, data: StudyPeriodDates) -> HubbitResult<StudyPeriod> {
+    let period_num: i32 = data.period.into();
+    Ok(
+      sqlx::query_as!(
+        StudyPeriod,
+        "
+INSERT INTO study_periods (year, period, start_date, end_date)
+VALUES ($1, $2, $3, $4)
+RETURNING *
+        ",
+        data.year,
+        period_num,
+        data.start_date,
+        data.end_date
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn update(&self, id: Uuid, data: StudyPeriodDates) -> HubbitResult<StudyPeriod> {
+    let period_num: i32 = data.period.into();
+    Ok(
+      sqlx::query_as!(
+        StudyPeriod,
+        "
+UPDATE study_periods
+SET
+  year = $2,
+  period = $3,
+  start_date = $4,
+  end_date = $5
+WHERE id = $1
+RETURNING *
+        ",
+        id,
+        data.year,
+        period_num,
+        data.start_date,
+        data.end_date
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Returns whether the study period existed
+  pub async fn delete(&self, id: Uuid) -> HubbitResult<bool> {
+    let result = sqlx::query!(
+      "
+DELETE FROM study_periods
+WHERE id = $1
+      ",
+      id
+    )
+    .execute(&self.pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+  }
+}
+
+pub struct StudyPeriodDates {
+  pub year: i32,
+  pub period: Period,
+  pub start_date: NaiveDate,
+  pub end_date: NaiveDate,
 }
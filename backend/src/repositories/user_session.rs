@@ -1,10 +1,16 @@
+use chrono::Duration;
 use sqlx::{
-  types::chrono::{DateTime, Local},
-  PgPool,
+  types::chrono::{DateTime, Local, Utc},
+  PgPool, Postgres, Transaction,
 };
 use uuid::Uuid;
 
-use crate::{error::HubbitResult, models::UserSession};
+use crate::{
+  error::HubbitResult,
+  models::{SessionTimeouts, UserSession},
+};
+
+use super::util::merge_sighting;
 
 #[derive(Clone, Debug)]
 pub struct UserSessionRepository {
@@ -20,6 +26,7 @@ impl UserSessionRepository {
     &self,
     start_time: DateTime<Local>,
     end_time: DateTime<Local>,
+    location_id: Option<Uuid>,
   ) -> HubbitResult<Vec<UserSession>> {
     Ok(
       sqlx::query_as!(
@@ -27,11 +34,14 @@ impl UserSessionRepository {
         "
 SELECT *
 FROM user_sessions
-WHERE end_time > $1 AND start_time < $2
+WHERE GREATEST(end_time, LEAST(expires_at, NOW())) > $1
+  AND start_time < $2
+  AND ($3::uuid IS NULL OR location_id = $3)
 ORDER BY start_time DESC
         ",
         start_time,
-        end_time
+        end_time,
+        location_id
       )
       .fetch_all(&self.pool)
       .await?,
@@ -50,7 +60,7 @@ ORDER BY start_time DESC
         "
 SELECT *
 FROM user_sessions
-WHERE user_id = $1 AND end_time > $2 AND start_time < $3
+WHERE user_id = $1 AND GREATEST(end_time, LEAST(expires_at, NOW())) > $2 AND start_time < $3
 ORDER BY start_time DESC
         ",
         user_id,
@@ -62,56 +72,362 @@ ORDER BY start_time DESC
     )
   }
 
-  pub async fn get_active(&self) -> HubbitResult<Vec<UserSession>> {
+  /// Sessions are active during the grace period of their location, which is
+  /// the most lenient one of its API keys, or the default if it has none
+  pub async fn get_active(
+    &self,
+    location_id: Option<Uuid>,
+    defaults: SessionTimeouts,
+  ) -> HubbitResult<Vec<UserSession>> {
     Ok(
       sqlx::query_as!(
         UserSession,
         "
-SELECT *
+SELECT user_sessions.*
 FROM user_sessions
-WHERE end_time + (10 * interval '1 minute') > NOW()
+LEFT JOIN (
+  SELECT location_id, MAX(COALESCE(session_grace_minutes, $2)) AS grace_minutes
+  FROM api_keys
+  GROUP BY location_id
+) AS location_timeouts ON location_timeouts.location_id = user_sessions.location_id
+WHERE expires_at + (COALESCE(location_timeouts.grace_minutes, $2::INTEGER) * interval '1 minute') > NOW()
+  AND ($1::uuid IS NULL OR user_sessions.location_id = $1)
 ORDER BY start_time DESC
         ",
+        location_id,
+        defaults.grace_minutes
       )
       .fetch_all(&self.pool)
       .await?,
     )
   }
 
-  pub async fn update_sessions(&self, user_ids: &[Uuid]) -> HubbitResult<()> {
-    let active_sessions: Vec<UserSession> = sqlx::query_as!(
+  /// Serializes updates to the sessions of the users across locations, until
+  /// the transaction ends. All users that a transaction updates have to be
+  /// locked at once, so that transactions can't deadlock each other.
+  pub async fn lock_users(
+    &self,
+    user_ids: &[Uuid],
+    tx: &mut Transaction<'_, Postgres>,
+  ) -> HubbitResult<()> {
+    let mut keys = user_ids
+      .iter()
+      .map(|user_id| {
+        let mut key = [0; 8];
+        key.copy_from_slice(&user_id.as_bytes()[..8]);
+        i64::from_be_bytes(key)
+      })
+      .collect::<Vec<_>>();
+    keys.sort_unstable();
+    keys.dedup();
+    sqlx::query!(
+      "
+SELECT pg_advisory_xact_lock(key) AS \"locked!: ()\"
+FROM (SELECT key FROM UNNEST($1::BIGINT[]) AS key ORDER BY key) AS keys
+      ",
+      &keys
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    Ok(())
+  }
+
+  /// Records that the users were seen in a location at `seen_at`, which may be
+  /// in the past for reports that were buffered by the reporter. A user is in
+  /// one place at a time, so their sessions in other locations end where this
+  /// one starts, and this one ends where a later one in another location
+  /// starts. Concurrent updates for the same location must be serialized by
+  /// the caller, see `LocationRepository::lock`, and so must updates for the
+  /// same users, see `lock_users`.
+  pub async fn update_sessions(
+    &self,
+    user_ids: &[Uuid],
+    location_id: Uuid,
+    timeouts: SessionTimeouts,
+    seen_at: DateTime<Utc>,
+    tx: &mut Transaction<'_, Postgres>,
+  ) -> HubbitResult<()> {
+    let expires_at = seen_at + Duration::minutes(timeouts.timeout_minutes.into());
+    // Every session that the new sighting would overlap is merged into it, so
+    // a new session never overlaps an existing one
+    let nearby_sessions: Vec<UserSession> = sqlx::query_as!(
       UserSession,
       "
-UPDATE user_sessions
-SET end_time = NOW() + (5 * interval '1 minute')
-WHERE user_id = ANY($1) AND end_time + (10 * interval '1 minute') > NOW()
-RETURNING *
+SELECT *
+FROM user_sessions
+WHERE user_id = ANY($1)
+  AND location_id = $2
+  AND start_time - ($5::INTEGER * interval '1 minute') <= $4
+  AND expires_at + ($5::INTEGER * interval '1 minute') >= $3
       ",
-      user_ids
+      user_ids,
+      location_id,
+      seen_at,
+      expires_at,
+      timeouts.grace_minutes
     )
-    .fetch_all(&self.pool)
+    .fetch_all(&mut *tx)
     .await?;
 
-    let inactive_user_ids = user_ids
+    // Only sessions in other locations that are within the span that the
+    // sighting could be merged into can be in its way
+    let window_start = nearby_sessions
       .iter()
-      .filter(|&user_id| {
-        !active_sessions
+      .map(|session| session.start_time)
+      .fold(seen_at, DateTime::min);
+    let window_end = nearby_sessions
+      .iter()
+      .map(|session| session.expires_at)
+      .fold(expires_at, DateTime::max);
+    let elsewhere_sessions: Vec<UserSession> = sqlx::query_as!(
+      UserSession,
+      "
+SELECT *
+FROM user_sessions
+WHERE user_id = ANY($1)
+  AND location_id <> $2
+  AND start_time < $4
+  AND expires_at > $3
+      ",
+      user_ids,
+      location_id,
+      window_start,
+      window_end
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut merged_sessions = Vec::new();
+    let mut inactive_users = Vec::new();
+    let mut cut_ids = Vec::new();
+    for &user_id in user_ids {
+      let elsewhere = elsewhere_sessions
+        .iter()
+        .filter(|session| session.user_id == user_id)
+        .collect::<Vec<_>>();
+      // When the user last arrived elsewhere before the sighting, and when they
+      // arrived elsewhere after it, if this is a sighting buffered by the
+      // reporter
+      let arrived_before = elsewhere
+        .iter()
+        .map(|session| session.start_time)
+        .filter(|&start_time| start_time < seen_at)
+        .max();
+      let left_at = elsewhere
+        .iter()
+        .map(|session| session.start_time)
+        .filter(|&start_time| start_time >= seen_at)
+        .min();
+      if left_at == Some(seen_at) {
+        continue;
+      }
+      let user_expires_at = left_at.map_or(expires_at, |left_at| left_at.min(expires_at));
+      cut_ids.extend(
+        elsewhere
           .iter()
-          .any(|active_sesh| active_sesh.user_id == *user_id)
-      })
-      .map(|user_id| user_id.to_owned())
-      .collect::<Vec<Uuid>>();
+          .filter(|session| session.start_time < seen_at && session.expires_at > seen_at)
+          .map(|session| session.id),
+      );
+
+      // Merging into sessions on the other side of a stay elsewhere would
+      // overlap it
+      let sessions = nearby_sessions
+        .iter()
+        .filter(|session| session.user_id == user_id)
+        .filter(|session| arrived_before.map_or(true, |arrived_at| session.start_time > arrived_at))
+        .filter(|session| left_at.map_or(true, |left_at| session.start_time < left_at))
+        .map(|session| {
+          (
+            session.id,
+            session.start_time,
+            session.end_time,
+            session.expires_at,
+          )
+        })
+        .collect::<Vec<_>>();
+      match merge_sighting(&sessions, seen_at, user_expires_at) {
+        Some(merged_session) => merged_sessions.push(merged_session),
+        None => inactive_users.push((user_id, user_expires_at)),
+      }
+    }
+
+    sqlx::query!(
+      "
+UPDATE user_sessions
+SET
+  end_time = LEAST(end_time, $2),
+  expires_at = $2
+WHERE id = ANY($1)
+      ",
+      &cut_ids,
+      seen_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let ids = merged_sessions
+      .iter()
+      .map(|session| session.id)
+      .collect::<Vec<_>>();
+    let start_times = merged_sessions
+      .iter()
+      .map(|session| session.start_time)
+      .collect::<Vec<_>>();
+    let end_times = merged_sessions
+      .iter()
+      .map(|session| session.end_time)
+      .collect::<Vec<_>>();
+    let expires_ats = merged_sessions
+      .iter()
+      .map(|session| session.expires_at)
+      .collect::<Vec<_>>();
+    let merged_ids = merged_sessions
+      .iter()
+      .flat_map(|session| session.merged_ids.iter().copied())
+      .collect::<Vec<_>>();
+
+    sqlx::query!(
+      "
+DELETE FROM user_sessions
+WHERE id = ANY($1)
+      ",
+      &merged_ids
+    )
+    .execute(&mut *tx)
+    .await?;
 
     sqlx::query!(
       "
-INSERT INTO user_sessions (user_id, start_time, end_time)
-SELECT user_id, NOW(), NOW() + (5 * interval '1 minute')
-FROM UNNEST($1::uuid[]) as user_id
+UPDATE user_sessions
+SET
+  start_time = data.start_time,
+  end_time = data.end_time,
+  expires_at = data.expires_at
+FROM UNNEST($1::uuid[], $2::TIMESTAMPTZ[], $3::TIMESTAMPTZ[], $4::TIMESTAMPTZ[])
+  as data(id, start_time, end_time, expires_at)
+WHERE user_sessions.id = data.id
       ",
-      &inactive_user_ids
+      &ids,
+      &start_times,
+      &end_times,
+      &expires_ats
     )
-    .fetch_all(&self.pool)
+    .execute(&mut *tx)
+    .await?;
+
+    let inactive_user_ids = inactive_users
+      .iter()
+      .map(|(user_id, _)| *user_id)
+      .collect::<Vec<_>>();
+    let inactive_expires_ats = inactive_users
+      .iter()
+      .map(|(_, expires_at)| *expires_at)
+      .collect::<Vec<_>>();
+    sqlx::query!(
+      "
+INSERT INTO user_sessions (user_id, start_time, end_time, expires_at, location_id)
+SELECT data.user_id, $3, $3, data.expires_at, $4
+FROM UNNEST($1::uuid[], $2::TIMESTAMPTZ[]) as data(user_id, expires_at)
+      ",
+      &inactive_user_ids,
+      &inactive_expires_ats,
+      seen_at,
+      location_id
+    )
+    .execute(&mut *tx)
     .await?;
     Ok(())
   }
+
+  /// Ends the sessions of the users in a location at `ended_at`, after the
+  /// sessions of their devices there have been ended. Users that still have
+  /// another device present stay until that device's session expires, but
+  /// never longer than they already would have.
+  pub async fn end_sessions(
+    &self,
+    user_ids: &[Uuid],
+    location_id: Uuid,
+    ended_at: DateTime<Utc>,
+    tx: &mut Transaction<'_, Postgres>,
+  ) -> HubbitResult<()> {
+    sqlx::query!(
+      "
+UPDATE user_sessions
+SET
+  end_time = $3,
+  expires_at = LEAST(expires_at, GREATEST($3, (
+    SELECT MAX(sessions.expires_at)
+    FROM sessions
+    JOIN devices ON devices.address = sessions.mac_address
+    WHERE sessions.user_id = user_sessions.user_id
+      AND sessions.location_id = user_sessions.location_id
+      AND sessions.start_time <= $3
+      AND sessions.expires_at > $3
+      AND NOT devices.exclude_from_presence
+      AND NOT devices.excluded_by_admin
+  )))
+WHERE user_id = ANY($1)
+  AND location_id = $2
+  AND start_time <= $3
+  AND end_time <= $3
+  AND expires_at >= $3
+      ",
+      user_ids,
+      location_id,
+      ended_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+  }
+
+  /// Merges overlapping sessions of a user in a location, returning the
+  /// number of sessions that were merged away
+  pub async fn merge_overlapping(&self) -> HubbitResult<i32> {
+    let merged = sqlx::query!(
+      "
+SELECT merge_overlapping_user_sessions() AS \"merged_count!\"
+      "
+    )
+    .fetch_one(&self.pool)
+    .await?;
+    Ok(merged.merged_count)
+  }
+
+  /// Ends the session now, if it hasn't already ended. Sessions of reports
+  /// timestamped slightly in the future can't end before they start.
+  pub async fn end(&self, id: Uuid) -> HubbitResult<UserSession> {
+    Ok(
+      sqlx::query_as!(
+        UserSession,
+        "
+UPDATE user_sessions
+SET
+  end_time = GREATEST(start_time, LEAST(end_time, NOW())),
+  expires_at = GREATEST(start_time, LEAST(expires_at, NOW()))
+WHERE id = $1
+RETURNING *
+        ",
+        id
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+
+  pub async fn delete(&self, id: Uuid) -> HubbitResult<UserSession> {
+    Ok(
+      sqlx::query_as!(
+        UserSession,
+        "
+DELETE FROM user_sessions
+WHERE id = $1
+RETURNING *
+        ",
+        id
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
 }
@@ -0,0 +1,114 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  error::HubbitResult,
+  models::{PrivacyMode, Theme, UserSettings},
+};
+
+#[derive(Clone, Debug)]
+pub struct UserSettingsRepository {
+  pool: PgPool,
+}
+
+impl UserSettingsRepository {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+
+  /// Returns `None` for users that have never changed their settings
+  pub async fn get_for_user(&self, user_id: Uuid) -> HubbitResult<Option<UserSettings>> {
+    Ok(
+      sqlx::query_as!(
+        UserSettings,
+        "
+SELECT *
+FROM user_settings
+WHERE user_id = $1
+        ",
+        user_id
+      )
+      .fetch_optional(&self.pool)
+      .await?,
+    )
+  }
+
+  /// Returns the users whose privacy mode is at least as strict as `privacy_mode`
+  pub async fn get_user_ids_with_privacy_mode(
+    &self,
+    privacy_mode: PrivacyMode,
+  ) -> HubbitResult<Vec<Uuid>> {
+    Ok(
+      sqlx::query!(
+        "
+SELECT user_id
+FROM user_settings
+WHERE privacy_mode >= $1
+        ",
+        i32::from(privacy_mode)
+      )
+      .fetch_all(&self.pool)
+      .await?
+      .into_iter()
+      .map(|row| row.user_id)
+      .collect(),
+    )
+  }
+
+  /// Creates the settings of the user if they don't exist, starting from the
+  /// defaults
+  pub async fn update(
+    &self,
+    user_id: Uuid,
+    data: UpdateUserSettings,
+  ) -> HubbitResult<UserSettings> {
+    let (set_display_name, display_name) = match data.display_name {
+      Some(display_name) => (true, display_name),
+      None => (false, None),
+    };
+    Ok(
+      sqlx::query_as!(
+        UserSettings,
+        "
+INSERT INTO user_settings (
+  user_id,
+  privacy_mode,
+  display_name,
+  theme,
+  notify_weekly_summary,
+  notify_device_registered
+)
+VALUES ($1, COALESCE($2, 0), $4, COALESCE($5, 0), COALESCE($6, FALSE), COALESCE($7, TRUE))
+ON CONFLICT (user_id) DO UPDATE
+SET
+  privacy_mode = COALESCE($2, user_settings.privacy_mode),
+  display_name = CASE WHEN $3 THEN $4 ELSE user_settings.display_name END,
+  theme = COALESCE($5, user_settings.theme),
+  notify_weekly_summary = COALESCE($6, user_settings.notify_weekly_summary),
+  notify_device_registered = COALESCE($7, user_settings.notify_device_registered)
+RETURNING *
+        ",
+        user_id,
+        data.privacy_mode.map(i32::from),
+        set_display_name,
+        display_name,
+        data.theme.map(i32::from),
+        data.notify_weekly_summary,
+        data.notify_device_registered
+      )
+      .fetch_one(&self.pool)
+      .await?,
+    )
+  }
+}
+
+/// Fields that are `None` are left unchanged
+#[derive(Default)]
+pub struct UpdateUserSettings {
+  pub privacy_mode: Option<PrivacyMode>,
+  /// `Some(None)` clears the display name
+  pub display_name: Option<Option<String>>,
+  pub theme: Option<Theme>,
+  pub notify_weekly_summary: Option<bool>,
+  pub notify_device_registered: Option<bool>,
+}
@@ -0,0 +1,45 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// The result of merging a sighting into the sessions it is close enough to
+/// continue. The session with id `id` is extended, and the sessions in
+/// `merged_ids` are swallowed by it and should be removed.
+pub struct MergedSession {
+  pub id: Uuid,
+  pub start_time: DateTime<Utc>,
+  pub end_time: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+  pub merged_ids: Vec<Uuid>,
+}
+
+/// The span of a session as `(id, start_time, end_time, expires_at)`
+pub type SessionSpan = (Uuid, DateTime<Utc>, DateTime<Utc>, DateTime<Utc>);
+
+/// Merges a sighting at `seen_at` into `sessions`. Returns `None` if there are no
+/// sessions to continue, in which case a new session should be started.
+pub fn merge_sighting(
+  sessions: &[SessionSpan],
+  seen_at: DateTime<Utc>,
+  expires_at: DateTime<Utc>,
+) -> Option<MergedSession> {
+  let &(id, _, _, _) = sessions
+    .iter()
+    .min_by_key(|&&(_, start_time, _, _)| start_time)?;
+  let mut merged = MergedSession {
+    id,
+    start_time: seen_at,
+    end_time: seen_at,
+    expires_at,
+    merged_ids: Vec::new(),
+  };
+  for &(session_id, start_time, end_time, session_expires_at) in sessions {
+    merged.start_time = merged.start_time.min(start_time);
+    merged.end_time = merged.end_time.max(end_time);
+    merged.expires_at = merged.expires_at.max(session_expires_at);
+    if session_id != id {
+      merged.merged_ids.push(session_id);
+    }
+  }
+
+  Some(merged)
+}
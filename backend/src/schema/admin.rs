@@ -0,0 +1,487 @@
+use async_graphql::{guard::Guard, Context, InputObject, Object, SimpleObject};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use log::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+  error::HubbitError,
+  models::{self, ApiKeyScope, Period},
+  repositories::{
+    api_key::{ApiKeyRepository, ApiKeySettings},
+    device::DeviceRepository,
+    study_period::{StudyPeriodDates, StudyPeriodRepository},
+    user_session::UserSessionRepository,
+  },
+  services::stats::StatsService,
+  utils::{token::generate_token, MAX_DATETIME, MIN_DATETIME},
+};
+
+use super::{location::Location, user::User, AdminGuard, HubbitSchemaError, HubbitSchemaResult};
+
+#[derive(Default)]
+pub struct AdminQuery;
+
+#[Object]
+impl AdminQuery {
+  #[graphql(guard(AdminGuard()))]
+  pub async fn api_keys(&self, context: &Context<'_>) -> HubbitSchemaResult<Vec<ApiKey>> {
+    let api_key_repo = context.data_unchecked::<ApiKeyRepository>();
+    let api_keys = api_key_repo.get_all().await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(api_keys.into_iter().map(ApiKey::from).collect())
+  }
+
+  #[graphql(guard(AdminGuard()))]
+  pub async fn study_periods(&self, context: &Context<'_>) -> HubbitSchemaResult<Vec<StudyPeriod>> {
+    let study_period_repo = context.data_unchecked::<StudyPeriodRepository>();
+    let study_periods = study_period_repo.get_all().await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(study_periods.into_iter().map(StudyPeriod::from).collect())
+  }
+
+  /// All sessions of the user, including ongoing ones, latest first
+  #[graphql(guard(AdminGuard()))]
+  pub async fn user_sessions(
+    &self,
+    context: &Context<'_>,
+    user_id: Uuid,
+  ) -> HubbitSchemaResult<Vec<UserSession>> {
+    let user_session_repo = context.data_unchecked::<UserSessionRepository>();
+    let sessions = user_session_repo
+      .get_range_for_user(*MIN_DATETIME, *MAX_DATETIME, user_id)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    Ok(sessions.into_iter().map(UserSession::from).collect())
+  }
+}
+
+#[derive(Default)]
+pub struct AdminMutation;
+
+#[Object]
+impl AdminMutation {
+  /// The token of the key is only returned here, it can't be read afterwards
+  #[graphql(guard(AdminGuard()))]
+  pub async fn create_api_key(
+    &self,
+    context: &Context<'_>,
+    data: ApiKeyInput,
+  ) -> HubbitSchemaResult<CreatedApiKey> {
+    let api_key_repo = context.data_unchecked::<ApiKeyRepository>();
+    let token = generate_token();
+    let api_key = api_key_repo
+      .create(&token, data.validate()?)
+      .await
+      .map_err(map_api_key_write_error)?;
+    Ok(CreatedApiKey {
+      api_key: ApiKey::from(api_key),
+      token,
+    })
+  }
+
+  #[graphql(guard(AdminGuard()))]
+  pub async fn update_api_key(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+    data: ApiKeyInput,
+  ) -> HubbitSchemaResult<ApiKey> {
+    let api_key_repo = context.data_unchecked::<ApiKeyRepository>();
+    let api_key = api_key_repo
+      .update(id, data.validate()?)
+      .await
+      .map_err(map_api_key_write_error)?;
+    Ok(ApiKey::from(api_key))
+  }
+
+  /// Gives the key a new token, which is only returned here. The previous token
+  /// stops working immediately.
+  #[graphql(guard(AdminGuard()))]
+  pub async fn rotate_api_key(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<CreatedApiKey> {
+    let api_key_repo = context.data_unchecked::<ApiKeyRepository>();
+    let token = generate_token();
+    let api_key = api_key_repo
+      .rotate(id, &token)
+      .await
+      .map_err(map_not_found)?;
+    Ok(CreatedApiKey {
+      api_key: ApiKey::from(api_key),
+      token,
+    })
+  }
+
+  /// Requires reports made with the key to be signed, with a new secret that
+  /// is only returned here
+  #[graphql(guard(AdminGuard()))]
+  pub async fn require_api_key_signature(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<ApiKeySigningSecret> {
+    let api_key_repo = context.data_unchecked::<ApiKeyRepository>();
+    let signing_secret = generate_token();
+    let api_key = api_key_repo
+      .set_signing_secret(id, Some(&signing_secret))
+      .await
+      .map_err(map_not_found)?;
+    Ok(ApiKeySigningSecret {
+      api_key: ApiKey::from(api_key),
+      signing_secret,
+    })
+  }
+
+  #[graphql(guard(AdminGuard()))]
+  pub async fn remove_api_key_signature(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<ApiKey> {
+    let api_key_repo = context.data_unchecked::<ApiKeyRepository>();
+    let api_key = api_key_repo
+      .set_signing_secret(id, None)
+      .await
+      .map_err(map_not_found)?;
+    Ok(ApiKey::from(api_key))
+  }
+
+  #[graphql(guard(AdminGuard()))]
+  pub async fn revoke_api_key(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<ApiKey> {
+    let api_key_repo = context.data_unchecked::<ApiKeyRepository>();
+    let api_key = api_key_repo.revoke(id).await.map_err(map_not_found)?;
+    Ok(ApiKey::from(api_key))
+  }
+
+  #[graphql(guard(AdminGuard()))]
+  pub async fn create_study_period(
+    &self,
+    context: &Context<'_>,
+    data: StudyPeriodInput,
+  ) -> HubbitSchemaResult<StudyPeriod> {
+    let study_period_repo = context.data_unchecked::<StudyPeriodRepository>();
+    let study_period = study_period_repo
+      .create(data.validate()?)
+      .await
+      .map_err(map_study_period_write_error)?;
+    Ok(StudyPeriod::from(study_period))
+  }
+
+  #[graphql(guard(AdminGuard()))]
+  pub async fn update_study_period(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+    data: StudyPeriodInput,
+  ) -> HubbitSchemaResult<StudyPeriod> {
+    let study_period_repo = context.data_unchecked::<StudyPeriodRepository>();
+    let study_period = study_period_repo
+      .update(id, data.validate()?)
+      .await
+      .map_err(map_study_period_write_error)?;
+    Ok(StudyPeriod::from(study_period))
+  }
+
+  #[graphql(guard(AdminGuard()))]
+  pub async fn delete_study_period(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<bool> {
+    let study_period_repo = context.data_unchecked::<StudyPeriodRepository>();
+    study_period_repo.delete(id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })
+  }
+
+  /// Removes any user's device
+  #[graphql(guard(AdminGuard()))]
+  pub async fn force_remove_device(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<bool> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(id).await.map_err(map_not_found)?;
+    device_repo.delete(&device.address).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(true)
+  }
+
+  /// Ends an ongoing session now, e.g. when a device was left behind. The
+  /// session is continued if the user is seen again within the grace period,
+  /// so exclude the device with `forceDeviceExcluded` if it is still there.
+  #[graphql(guard(AdminGuard()))]
+  pub async fn end_user_session(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<UserSession> {
+    let user_session_repo = context.data_unchecked::<UserSessionRepository>();
+    let session = user_session_repo.end(id).await.map_err(map_not_found)?;
+    invalidate_session_stats(context, &session).await;
+    Ok(UserSession::from(session))
+  }
+
+  /// Removes a session that shouldn't count, e.g. one caused by a
+  /// misconfigured device
+  #[graphql(guard(AdminGuard()))]
+  pub async fn delete_user_session(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<bool> {
+    let user_session_repo = context.data_unchecked::<UserSessionRepository>();
+    let session = user_session_repo.delete(id).await.map_err(map_not_found)?;
+    invalidate_session_stats(context, &session).await;
+    Ok(true)
+  }
+}
+
+#[derive(SimpleObject)]
+pub struct ApiKey {
+  id: Uuid,
+  name: String,
+  scopes: Vec<ApiKeyScope>,
+  location: Location,
+  min_signal_strength: Option<i32>,
+  session_timeout_minutes: Option<i32>,
+  session_grace_minutes: Option<i32>,
+  expires_at: Option<DateTime<Utc>>,
+  last_used_at: Option<DateTime<Utc>>,
+  revoked_at: Option<DateTime<Utc>>,
+  /// Whether reports made with the key have to be signed
+  requires_signature: bool,
+  /// The MQTT topic that reports made with the key are published to, if any
+  mqtt_topic: Option<String>,
+  created_at: DateTime<Utc>,
+}
+
+impl From<models::ApiKey> for ApiKey {
+  fn from(api_key: models::ApiKey) -> Self {
+    Self {
+      id: api_key.id,
+      name: api_key.name,
+      scopes: api_key.scopes.into_iter().map(ApiKeyScope::from).collect(),
+      location: Location {
+        id: api_key.location_id,
+      },
+      min_signal_strength: api_key.min_signal_strength,
+      session_timeout_minutes: api_key.session_timeout_minutes,
+      session_grace_minutes: api_key.session_grace_minutes,
+      expires_at: api_key.expires_at,
+      last_used_at: api_key.last_used_at,
+      revoked_at: api_key.revoked_at,
+      requires_signature: api_key.signing_secret.is_some(),
+      mqtt_topic: api_key.mqtt_topic,
+      created_at: api_key.created_at,
+    }
+  }
+}
+
+#[derive(SimpleObject)]
+pub struct CreatedApiKey {
+  api_key: ApiKey,
+  token: String,
+}
+
+#[derive(SimpleObject)]
+pub struct ApiKeySigningSecret {
+  api_key: ApiKey,
+  signing_secret: String,
+}
+
+/// Settings left out fall back to the defaults of the server. Keys without an
+/// expiry work until they are revoked.
+#[derive(InputObject)]
+pub struct ApiKeyInput {
+  name: String,
+  scopes: Vec<ApiKeyScope>,
+  expires_at: Option<DateTime<Utc>>,
+  location_id: Uuid,
+  min_signal_strength: Option<i32>,
+  session_timeout_minutes: Option<i32>,
+  session_grace_minutes: Option<i32>,
+  mqtt_topic: Option<String>,
+}
+
+impl ApiKeyInput {
+  fn validate(self) -> HubbitSchemaResult<ApiKeySettings> {
+    let name = self.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 64 || self.scopes.is_empty() {
+      return Err(HubbitSchemaError::InvalidInput);
+    }
+
+    // Reports are attributed to a key by the exact topic they are published to
+    let mqtt_topic = self.mqtt_topic.map(|topic| topic.trim().to_string());
+    if mqtt_topic.as_ref().map_or(false, |topic| {
+      topic.is_empty() || topic.len() > 256 || topic.contains(|c| c == '+' || c == '#')
+    }) {
+      return Err(HubbitSchemaError::InvalidInput);
+    }
+
+    if self
+      .session_timeout_minutes
+      .map_or(false, |minutes| minutes <= 0)
+      || self
+        .session_grace_minutes
+        .map_or(false, |minutes| minutes < 0)
+    {
+      return Err(HubbitSchemaError::InvalidInput);
+    }
+
+    let mut scopes = self.scopes.into_iter().map(i32::from).collect::<Vec<_>>();
+    scopes.sort_unstable();
+    scopes.dedup();
+    Ok(ApiKeySettings {
+      name,
+      scopes,
+      expires_at: self.expires_at,
+      location_id: self.location_id,
+      min_signal_strength: self.min_signal_strength,
+      session_timeout_minutes: self.session_timeout_minutes,
+      session_grace_minutes: self.session_grace_minutes,
+      mqtt_topic,
+    })
+  }
+}
+
+#[derive(SimpleObject)]
+pub struct StudyPeriod {
+  id: Uuid,
+  year: i32,
+  period: Period,
+  start_date: NaiveDate,
+  end_date: NaiveDate,
+}
+
+impl From<models::StudyPeriod> for StudyPeriod {
+  fn from(study_period: models::StudyPeriod) -> Self {
+    Self {
+      id: study_period.id,
+      year: study_period.year,
+      period: Period::from(study_period.period),
+      start_date: study_period.start_date,
+      end_date: study_period.end_date,
+    }
+  }
+}
+
+#[derive(InputObject)]
+pub struct StudyPeriodInput {
+  year: i32,
+  period: Period,
+  start_date: NaiveDate,
+  end_date: NaiveDate,
+}
+
+impl StudyPeriodInput {
+  fn validate(self) -> HubbitSchemaResult<StudyPeriodDates> {
+    if self.start_date >= self.end_date {
+      return Err(HubbitSchemaError::InvalidInput);
+    }
+
+    Ok(StudyPeriodDates {
+      year: self.year,
+      period: self.period,
+      start_date: self.start_date,
+      end_date: self.end_date,
+    })
+  }
+}
+
+#[derive(SimpleObject)]
+pub struct UserSession {
+  id: Uuid,
+  user: User,
+  location: Location,
+  start_time: DateTime<Utc>,
+  end_time: DateTime<Utc>,
+  expires_at: DateTime<Utc>,
+}
+
+impl From<models::UserSession> for UserSession {
+  fn from(session: models::UserSession) -> Self {
+    Self {
+      id: session.id,
+      user: User {
+        id: session.user_id,
+      },
+      location: Location {
+        id: session.location_id,
+      },
+      start_time: session.start_time,
+      end_time: session.end_time,
+      expires_at: session.expires_at,
+    }
+  }
+}
+
+// Cached stats of the days that the session touched are no longer correct
+async fn invalidate_session_stats(context: &Context<'_>, session: &models::UserSession) {
+  let stats_service = context.data_unchecked::<StatsService>();
+  let start_date = session
+    .start_time
+    .with_timezone(&Local)
+    .date()
+    .naive_local();
+  let end_date = session
+    .expires_at
+    .with_timezone(&Local)
+    .date()
+    .naive_local();
+  if let Err(e) = stats_service
+    .invalidate_range(start_date, end_date, session.location_id)
+    .await
+  {
+    warn!("[Schema error] Could not invalidate cached stats: {:?}", e);
+  }
+}
+
+fn map_not_found(e: HubbitError) -> HubbitSchemaError {
+  match e {
+    HubbitError::SqlxError(sqlx::Error::RowNotFound) => HubbitSchemaError::NotFound,
+    e => {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    }
+  }
+}
+
+// The location of an API key has to exist, and its MQTT topic can't be used
+// by another key
+fn map_api_key_write_error(e: HubbitError) -> HubbitSchemaError {
+  if e.is_foreign_key_violation() {
+    return HubbitSchemaError::NotFound;
+  }
+  if e.is_unique_violation() {
+    return HubbitSchemaError::InvalidInput;
+  }
+
+  map_not_found(e)
+}
+
+// There can only be one study period of each kind per year
+fn map_study_period_write_error(e: HubbitError) -> HubbitSchemaError {
+  if e.is_unique_violation() {
+    return HubbitSchemaError::InvalidInput;
+  }
+
+  map_not_found(e)
+}
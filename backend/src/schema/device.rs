@@ -1,16 +1,27 @@
-use async_graphql::{guard::Guard, Context, InputObject, Object};
+use async_graphql::{guard::Guard, Context, Enum, InputObject, Object, SimpleObject};
+use chrono::{DateTime, Utc};
 use log::error;
 use uuid::Uuid;
 
 use crate::{
-  models::GammaUser,
+  config::Config,
+  error::HubbitError,
+  models::{self, DeviceSighting, GammaUser, TokenScope},
   repositories::{
     device::{CreateDevice, DeviceRepository, UpdateDevice},
+    device_sighting::DeviceSightingRepository,
     session::SessionRepository,
   },
+  utils::{
+    mac::{is_randomized_mac_addr, normalize_mac_addr},
+    oui::OuiTable,
+  },
 };
 
-use super::{AuthGuard, HubbitSchemaError, HubbitSchemaResult};
+use super::{location::Location, AdminGuard, HubbitSchemaError, HubbitSchemaResult, ScopeGuard};
+
+// How many of its latest sessions are listed for a device
+const RECENT_SESSIONS: i64 = 10;
 
 pub struct Device {
   pub id: Uuid,
@@ -40,6 +51,58 @@ impl Device {
     Ok(device.name)
   }
 
+  async fn vendor(&self, context: &Context<'_>) -> HubbitSchemaResult<Option<String>> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    let oui_table = context.data_unchecked::<OuiTable>();
+    Ok(oui_table.vendor(&device.address).map(str::to_owned))
+  }
+
+  /// A name based on the vendor, e.g. "Apple device"
+  async fn suggested_name(&self, context: &Context<'_>) -> HubbitSchemaResult<Option<String>> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    let oui_table = context.data_unchecked::<OuiTable>();
+    Ok(oui_table.suggested_name(&device.address))
+  }
+
+  /// Whether the address is randomized by the device, in which case it will
+  /// likely change and the device stop being tracked
+  async fn is_randomized_address(&self, context: &Context<'_>) -> HubbitSchemaResult<bool> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(is_randomized_mac_addr(&device.address))
+  }
+
+  /// Whether the user excluded the device from making them present
+  async fn exclude_from_presence(&self, context: &Context<'_>) -> HubbitSchemaResult<bool> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(device.exclude_from_presence)
+  }
+
+  /// Whether an admin excluded the device from making its user present
+  async fn excluded_by_admin(&self, context: &Context<'_>) -> HubbitSchemaResult<bool> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(device.excluded_by_admin)
+  }
+
   async fn is_active(&self, context: &Context<'_>) -> HubbitSchemaResult<bool> {
     let device_repo = context.data_unchecked::<DeviceRepository>();
     let device = device_repo.get_by_id(self.id).await.map_err(|e| {
@@ -47,8 +110,9 @@ impl Device {
       HubbitSchemaError::InternalError
     })?;
     let session_repo = context.data_unchecked::<SessionRepository>();
+    let config = context.data_unchecked::<Config>();
     let is_active = session_repo
-      .is_device_active(device.address)
+      .is_device_active(device.address, device.user_id, config.session_timeouts())
       .await
       .map_err(|e| {
         error!("[Schema error] {:?}", e);
@@ -56,6 +120,188 @@ impl Device {
       })?;
     Ok(is_active)
   }
+
+  async fn last_seen_signal(&self, context: &Context<'_>) -> HubbitSchemaResult<Option<i32>> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    let session_repo = context.data_unchecked::<SessionRepository>();
+    let session = session_repo
+      .get_latest_for_device(device.address, device.user_id)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    Ok(session.and_then(|session| session.signal_strength))
+  }
+
+  async fn last_seen(&self, context: &Context<'_>) -> HubbitSchemaResult<Option<DateTime<Utc>>> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    let session_repo = context.data_unchecked::<SessionRepository>();
+    let session = session_repo
+      .get_latest_for_device(device.address, device.user_id)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    Ok(session.map(|session| session.end_time))
+  }
+
+  async fn recent_sessions(&self, context: &Context<'_>) -> HubbitSchemaResult<Vec<DeviceSession>> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    let session_repo = context.data_unchecked::<SessionRepository>();
+    let sessions = session_repo
+      .get_for_device(device.address, device.user_id, Some(RECENT_SESSIONS))
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    Ok(
+      sessions
+        .iter()
+        .map(|session| DeviceSession {
+          start_time: session.start_time,
+          end_time: session.effective_end_time(),
+          location: Location {
+            id: session.location_id,
+          },
+          min_signal_strength: session.min_signal_strength,
+          max_signal_strength: session.max_signal_strength,
+        })
+        .collect(),
+    )
+  }
+
+  async fn total_time_seconds(&self, context: &Context<'_>) -> HubbitSchemaResult<i64> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    let session_repo = context.data_unchecked::<SessionRepository>();
+    let sessions = session_repo
+      .get_for_device(device.address, device.user_id, None)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+
+    let duration_ms = sessions.iter().fold(0, |prev, cur| {
+      prev + (cur.effective_end_time() - cur.start_time).num_milliseconds()
+    });
+
+    Ok(duration_ms / 1000)
+  }
+}
+
+#[derive(SimpleObject)]
+pub struct DeviceSession {
+  start_time: DateTime<Utc>,
+  end_time: DateTime<Utc>,
+  location: Location,
+  min_signal_strength: Option<i32>,
+  max_signal_strength: Option<i32>,
+}
+
+#[derive(SimpleObject)]
+pub struct AddDevicePayload {
+  device: Device,
+  warnings: Vec<DeviceWarning>,
+}
+
+#[derive(SimpleObject)]
+pub struct SetDevicesPayload {
+  devices: Vec<Device>,
+  warnings: Vec<DeviceWarning>,
+}
+
+/// Something about a registered device that the user should know
+#[derive(SimpleObject)]
+pub struct DeviceWarning {
+  address: String,
+  kind: DeviceWarningKind,
+}
+
+impl DeviceWarning {
+  fn for_address(address: &str) -> Option<Self> {
+    if is_randomized_mac_addr(address) {
+      return Some(Self {
+        address: address.to_owned(),
+        kind: DeviceWarningKind::RandomizedAddress,
+      });
+    }
+
+    None
+  }
+}
+
+#[derive(Copy, Clone, Debug, Enum, Eq, PartialEq)]
+pub enum DeviceWarningKind {
+  /// The address is randomized by the device, so it will likely change and the
+  /// device stop being tracked
+  RandomizedAddress,
+}
+
+/// A recently seen device that isn't registered by anyone
+#[derive(SimpleObject)]
+pub struct ClaimableDevice {
+  pub id: Uuid,
+  pub first_seen: DateTime<Utc>,
+  pub last_seen: DateTime<Utc>,
+  /// Not known if the device was only reported as connecting
+  pub signal_strength: Option<i32>,
+  pub location: Location,
+}
+
+impl From<DeviceSighting> for ClaimableDevice {
+  fn from(sighting: DeviceSighting) -> Self {
+    Self {
+      id: sighting.id,
+      first_seen: sighting.first_seen,
+      last_seen: sighting.last_seen,
+      signal_strength: sighting.signal_strength,
+      location: Location {
+        id: sighting.location_id,
+      },
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct DeviceQuery;
+
+#[Object]
+impl DeviceQuery {
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::ReadAccount")))]
+  pub async fn claimable_devices(
+    &self,
+    context: &Context<'_>,
+    location_id: Option<Uuid>,
+  ) -> HubbitSchemaResult<Vec<ClaimableDevice>> {
+    let device_sighting_repo = context.data_unchecked::<DeviceSightingRepository>();
+    let sightings = device_sighting_repo
+      .get_claimable(location_id)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    Ok(sightings.into_iter().map(ClaimableDevice::from).collect())
+  }
 }
 
 #[derive(Default)]
@@ -63,95 +309,243 @@ pub struct DeviceMutation;
 
 #[Object]
 impl DeviceMutation {
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::WriteAccount")))]
   pub async fn set_devices(
     &self,
     context: &Context<'_>,
     mut data: SetDevicesInput,
-  ) -> HubbitSchemaResult<Vec<Device>> {
+  ) -> HubbitSchemaResult<SetDevicesPayload> {
     for device in data.devices.iter_mut() {
-      device.address = device.address.to_uppercase();
-      validate_mac_addr(&device.address)?;
+      device.address =
+        normalize_mac_addr(&device.address).ok_or(HubbitSchemaError::InvalidAddress)?;
+      validate_device_name(&device.name)?;
+    }
+
+    // Addresses that are written differently may be the same once normalized
+    let mut addresses = data
+      .devices
+      .iter()
+      .map(|device| device.address.clone())
+      .collect::<Vec<_>>();
+    addresses.sort_unstable();
+    addresses.dedup();
+    if addresses.len() < data.devices.len() {
+      return Err(HubbitSchemaError::InvalidInput);
     }
 
     let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device_sighting_repo = context.data_unchecked::<DeviceSightingRepository>();
     let auth_user = context.data_unchecked::<GammaUser>();
-    let current_devices = device_repo.get_for_user(auth_user.id).await.map_err(|e| {
+    let config = context.data_unchecked::<Config>();
+    // Claimed addresses become devices the next time they are seen
+    let claimed = device_sighting_repo
+      .count_claimed(auth_user.id)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    if data.devices.len() + claimed as usize > config.max_devices_per_user {
+      return Err(HubbitSchemaError::TooManyDevices);
+    }
+
+    let existing_devices = device_repo.get_by_addrs(&addresses).await.map_err(|e| {
       error!("[Schema error] {:?}", e);
       HubbitSchemaError::InternalError
     })?;
-
-    let mut devices_to_create = Vec::new();
-    let mut devices_to_update = Vec::new();
-    let mut devices_to_remove = Vec::new();
-    for device in data.devices.iter().cloned() {
-      // If device doesn't exist, add to create list, else add to update list
-      if !current_devices.iter().any(|d| d.address == device.address) {
-        devices_to_create.push(device);
-      } else {
-        devices_to_update.push(device);
-      }
+    if existing_devices
+      .iter()
+      .any(|device| device.user_id != auth_user.id)
+    {
+      return Err(HubbitSchemaError::AddressBelongsToOtherUser);
     }
 
-    for device in current_devices.iter().cloned() {
-      // If device doesn't exist in list of new devices, add to remove list
-      if !data.devices.iter().any(|d| d.address == device.address) {
-        devices_to_remove.push(device);
-      }
-    }
+    let current_devices = device_repo
+      .replace_for_user(
+        auth_user.id,
+        data
+          .devices
+          .into_iter()
+          .map(|device| UpdateDevice {
+            address: device.address,
+            name: device.name,
+            exclude_from_presence: device.exclude_from_presence,
+          })
+          .collect(),
+      )
+      .await
+      .map_err(map_device_write_error)?;
+
+    Ok(SetDevicesPayload {
+      warnings: current_devices
+        .iter()
+        .filter_map(|d| DeviceWarning::for_address(&d.address))
+        .collect(),
+      devices: current_devices
+        .iter()
+        .map(|d| Device { id: d.id })
+        .collect(),
+    })
+  }
+
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::WriteAccount")))]
+  pub async fn add_device(
+    &self,
+    context: &Context<'_>,
+    mut data: DeviceInput,
+  ) -> HubbitSchemaResult<AddDevicePayload> {
+    data.address = normalize_mac_addr(&data.address).ok_or(HubbitSchemaError::InvalidAddress)?;
+    validate_device_name(&data.name)?;
 
-    for device in devices_to_remove {
-      device_repo.delete(&device.address).await.map_err(|e| {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let auth_user = context.data_unchecked::<GammaUser>();
+    check_device_limit(context, auth_user.id).await?;
+
+    let existing_devices = device_repo
+      .get_by_addrs(&[data.address.clone()])
+      .await
+      .map_err(|e| {
         error!("[Schema error] {:?}", e);
         HubbitSchemaError::InternalError
       })?;
+    if let Some(existing_device) = existing_devices.first() {
+      return Err(if existing_device.user_id == auth_user.id {
+        HubbitSchemaError::InvalidInput
+      } else {
+        HubbitSchemaError::AddressBelongsToOtherUser
+      });
     }
 
-    for device in devices_to_update {
-      let address = device.address.clone();
-      device_repo
-        .update(
-          &address,
-          UpdateDevice {
-            address: device.address,
-            name: device.name,
-          },
-        )
-        .await
-        .map_err(|e| {
-          error!("[Schema error] {:?}", e);
-          HubbitSchemaError::InternalError
-        })?;
-    }
+    let device = device_repo
+      .create(CreateDevice {
+        address: data.address,
+        name: data.name,
+        user_id: auth_user.id,
+        exclude_from_presence: data.exclude_from_presence.unwrap_or(false),
+      })
+      .await
+      .map_err(map_device_write_error)?;
+    Ok(AddDevicePayload {
+      device: Device { id: device.id },
+      warnings: DeviceWarning::for_address(&device.address)
+        .into_iter()
+        .collect(),
+    })
+  }
 
-    for device in devices_to_create {
-      device_repo
-        .create(CreateDevice {
-          address: device.address,
-          name: device.name,
-          user_id: auth_user.id,
-        })
-        .await
-        .map_err(|e| {
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::WriteAccount")))]
+  pub async fn rename_device(
+    &self,
+    context: &Context<'_>,
+    data: RenameDeviceInput,
+  ) -> HubbitSchemaResult<Device> {
+    validate_device_name(&data.name)?;
+
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let auth_user = context.data_unchecked::<GammaUser>();
+    get_own_device(device_repo, data.id, auth_user.id).await?;
+
+    let device = device_repo.rename(data.id, &data.name).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(Device { id: device.id })
+  }
+
+  /// Excludes the device from making the user present, while still tracking
+  /// the device itself
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::WriteAccount")))]
+  pub async fn set_device_excluded(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+    excluded: bool,
+  ) -> HubbitSchemaResult<Device> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let auth_user = context.data_unchecked::<GammaUser>();
+    get_own_device(device_repo, id, auth_user.id).await?;
+
+    let device = device_repo.set_excluded(id, excluded).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(Device { id: device.id })
+  }
+
+  /// Excludes any user's device from making its user present, which the user
+  /// can't override
+  #[graphql(guard(AdminGuard()))]
+  pub async fn force_device_excluded(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+    excluded: bool,
+  ) -> HubbitSchemaResult<Device> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let device = device_repo
+      .set_excluded_by_admin(id, excluded)
+      .await
+      .map_err(|e| match e {
+        HubbitError::SqlxError(sqlx::Error::RowNotFound) => HubbitSchemaError::NotFound,
+        e => {
           error!("[Schema error] {:?}", e);
           HubbitSchemaError::InternalError
-        })?;
-    }
+        }
+      })?;
+    Ok(Device { id: device.id })
+  }
+
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::WriteAccount")))]
+  pub async fn remove_device(&self, context: &Context<'_>, id: Uuid) -> HubbitSchemaResult<bool> {
+    let device_repo = context.data_unchecked::<DeviceRepository>();
+    let auth_user = context.data_unchecked::<GammaUser>();
+    let device = get_own_device(device_repo, id, auth_user.id).await?;
 
-    let current_devices = device_repo.get_for_user(auth_user.id).await.map_err(|e| {
+    device_repo.delete(&device.address).await.map_err(|e| {
       error!("[Schema error] {:?}", e);
       HubbitSchemaError::InternalError
     })?;
+    Ok(true)
+  }
 
-    Ok(
-      current_devices
-        .iter()
-        .map(|d| Device { id: d.id })
-        .collect(),
-    )
+  /// Claims a recently seen device, which is registered to the user the next
+  /// time it is seen
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::WriteAccount")))]
+  pub async fn claim_device(
+    &self,
+    context: &Context<'_>,
+    data: ClaimDeviceInput,
+  ) -> HubbitSchemaResult<ClaimableDevice> {
+    validate_device_name(&data.name)?;
+
+    let device_sighting_repo = context.data_unchecked::<DeviceSightingRepository>();
+    let auth_user = context.data_unchecked::<GammaUser>();
+    check_device_limit(context, auth_user.id).await?;
+
+    let sighting = device_sighting_repo
+      .claim(data.id, auth_user.id, &data.name)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?
+      .ok_or(HubbitSchemaError::NotFound)?;
+    Ok(ClaimableDevice::from(sighting))
   }
 }
 
+#[derive(InputObject)]
+pub struct RenameDeviceInput {
+  id: Uuid,
+  name: String,
+}
+
+#[derive(InputObject)]
+pub struct ClaimDeviceInput {
+  id: Uuid,
+  name: String,
+}
+
 #[derive(InputObject)]
 pub struct SetDevicesInput {
   devices: Vec<DeviceInput>,
@@ -161,24 +555,68 @@ pub struct SetDevicesInput {
 pub struct DeviceInput {
   address: String,
   name: String,
+  /// Left unchanged for existing devices if not given
+  exclude_from_presence: Option<bool>,
 }
 
-static VAILD_CHARS: &str = "1234567890ABCDEF";
-
-fn validate_mac_addr(raw_mac_addr: &str) -> HubbitSchemaResult<()> {
-  if raw_mac_addr.len() != 17 {
+fn validate_device_name(name: &str) -> HubbitSchemaResult<()> {
+  if name.trim().is_empty() || name.chars().count() > 64 {
     return Err(HubbitSchemaError::InvalidInput);
   }
 
-  for (i, c) in raw_mac_addr.chars().enumerate() {
-    if i % 3 == 2 {
-      if c != ':' {
-        return Err(HubbitSchemaError::InvalidInput);
-      }
-    } else if !VAILD_CHARS.contains(c) {
-      return Err(HubbitSchemaError::InvalidInput);
+  Ok(())
+}
+
+async fn get_own_device(
+  device_repo: &DeviceRepository,
+  id: Uuid,
+  user_id: Uuid,
+) -> HubbitSchemaResult<models::Device> {
+  let device = device_repo.get_by_id(id).await.map_err(|e| match e {
+    HubbitError::SqlxError(sqlx::Error::RowNotFound) => HubbitSchemaError::NotFound,
+    e => {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
     }
+  })?;
+
+  // Other users' devices are reported as missing, to not reveal their ids
+  if device.user_id != user_id {
+    return Err(HubbitSchemaError::NotFound);
+  }
+
+  Ok(device)
+}
+
+async fn check_device_limit(context: &Context<'_>, user_id: Uuid) -> HubbitSchemaResult<()> {
+  let device_repo = context.data_unchecked::<DeviceRepository>();
+  let device_sighting_repo = context.data_unchecked::<DeviceSightingRepository>();
+  let config = context.data_unchecked::<Config>();
+  let devices = device_repo.get_for_user(user_id).await.map_err(|e| {
+    error!("[Schema error] {:?}", e);
+    HubbitSchemaError::InternalError
+  })?;
+  // Claimed addresses become devices the next time they are seen
+  let claimed = device_sighting_repo
+    .count_claimed(user_id)
+    .await
+    .map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+  if devices.len() + claimed as usize >= config.max_devices_per_user {
+    return Err(HubbitSchemaError::TooManyDevices);
   }
 
   Ok(())
 }
+
+// The address can only be taken if another user registered it concurrently
+fn map_device_write_error(e: HubbitError) -> HubbitSchemaError {
+  if e.is_unique_violation() {
+    return HubbitSchemaError::AddressBelongsToOtherUser;
+  }
+
+  error!("[Schema error] {:?}", e);
+  HubbitSchemaError::InternalError
+}
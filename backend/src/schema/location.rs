@@ -0,0 +1,75 @@
+use async_graphql::{guard::Guard, Context, Object};
+use log::error;
+use uuid::Uuid;
+
+use crate::{
+  config::Config,
+  models::SessionTimeouts,
+  repositories::{api_key::ApiKeyRepository, location::LocationRepository},
+};
+
+use super::{HubbitSchemaError, HubbitSchemaResult, ReadGuard};
+
+#[derive(Default)]
+pub struct LocationQuery;
+
+#[Object]
+impl LocationQuery {
+  #[graphql(guard(ReadGuard()))]
+  pub async fn locations(&self, context: &Context<'_>) -> HubbitSchemaResult<Vec<Location>> {
+    let location_repo = context.data_unchecked::<LocationRepository>();
+    let locations = location_repo.get_all().await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(
+      locations
+        .into_iter()
+        .map(|location| Location { id: location.id })
+        .collect(),
+    )
+  }
+}
+
+pub struct Location {
+  pub id: Uuid,
+}
+
+#[Object]
+impl Location {
+  async fn id(&self) -> Uuid {
+    self.id
+  }
+
+  async fn name(&self, context: &Context<'_>) -> HubbitSchemaResult<String> {
+    let location_repo = context.data_unchecked::<LocationRepository>();
+    let location = location_repo.get_by_id(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+    Ok(location.name)
+  }
+
+  /// The timeouts of the reporters in this location, the most lenient one
+  /// being used if they differ
+  async fn session_timeouts(&self, context: &Context<'_>) -> HubbitSchemaResult<SessionTimeouts> {
+    let api_key_repo = context.data_unchecked::<ApiKeyRepository>();
+    let config = context.data_unchecked::<Config>();
+    let api_keys = api_key_repo.get_for_location(self.id).await.map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+
+    let defaults = config.session_timeouts();
+    Ok(
+      api_keys
+        .iter()
+        .map(|api_key| api_key.session_timeouts(defaults))
+        .reduce(|a, b| SessionTimeouts {
+          timeout_minutes: a.timeout_minutes.max(b.timeout_minutes),
+          grace_minutes: a.grace_minutes.max(b.grace_minutes),
+        })
+        .unwrap_or(defaults),
+    )
+  }
+}
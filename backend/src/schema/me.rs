@@ -1,17 +1,192 @@
-use async_graphql::{guard::Guard, Context, Object};
+use async_graphql::{guard::Guard, Context, InputObject, Object, SimpleObject};
+use chrono::{DateTime, Utc};
+use log::error;
+use uuid::Uuid;
 
-use crate::models::GammaUser;
+use crate::{
+  error::HubbitError,
+  models::{self, GammaUser, TokenScope},
+  repositories::personal_access_token::{CreatePersonalAccessToken, PersonalAccessTokenRepository},
+  services::auth::AuthService,
+  utils::token::{generate_token, PERSONAL_ACCESS_TOKEN_PREFIX},
+};
 
-use super::{user::User, AuthGuard};
+use super::{
+  settings::{get_settings, Settings},
+  user::User,
+  AuthGuard, HubbitSchemaError, HubbitSchemaResult, ScopeGuard,
+};
 
 #[derive(Default)]
 pub struct MeQuery;
 
+#[derive(Default)]
+pub struct MeMutation;
+
 #[Object]
 impl MeQuery {
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::ReadAccount")))]
   pub async fn me(&self, context: &Context<'_>) -> User {
     let user = context.data_unchecked::<GammaUser>();
     User { id: user.id }
   }
+
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::ReadAccount")))]
+  pub async fn settings(&self, context: &Context<'_>) -> HubbitSchemaResult<Settings> {
+    let user = context.data_unchecked::<GammaUser>();
+    get_settings(context, user.id).await
+  }
+
+  /// Every personal access token of the user, including revoked ones
+  #[graphql(guard(AuthGuard()))]
+  pub async fn personal_access_tokens(
+    &self,
+    context: &Context<'_>,
+  ) -> HubbitSchemaResult<Vec<PersonalAccessToken>> {
+    let personal_access_token_repo = context.data_unchecked::<PersonalAccessTokenRepository>();
+    let user = context.data_unchecked::<GammaUser>();
+    let personal_access_tokens = personal_access_token_repo
+      .get_for_user(user.id)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    Ok(
+      personal_access_tokens
+        .into_iter()
+        .map(PersonalAccessToken::from)
+        .collect(),
+    )
+  }
+}
+
+#[Object]
+impl MeMutation {
+  /// Ends every login session of the user, including the current one
+  #[graphql(guard(AuthGuard()))]
+  pub async fn log_out_everywhere(&self, context: &Context<'_>) -> HubbitSchemaResult<bool> {
+    let auth_service = context.data_unchecked::<AuthService>();
+    let user = context.data_unchecked::<GammaUser>();
+    auth_service
+      .delete_user_sessions(user.id)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    Ok(true)
+  }
+
+  /// The token is only returned here, it can't be read afterwards
+  #[graphql(guard(AuthGuard()))]
+  pub async fn create_personal_access_token(
+    &self,
+    context: &Context<'_>,
+    data: PersonalAccessTokenInput,
+  ) -> HubbitSchemaResult<CreatedPersonalAccessToken> {
+    let personal_access_token_repo = context.data_unchecked::<PersonalAccessTokenRepository>();
+    let user = context.data_unchecked::<GammaUser>();
+    let token = format!("{}{}", PERSONAL_ACCESS_TOKEN_PREFIX, generate_token());
+    let personal_access_token = personal_access_token_repo
+      .create(user.id, &token, data.validate()?)
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    Ok(CreatedPersonalAccessToken {
+      personal_access_token: PersonalAccessToken::from(personal_access_token),
+      token,
+    })
+  }
+
+  #[graphql(guard(AuthGuard()))]
+  pub async fn revoke_personal_access_token(
+    &self,
+    context: &Context<'_>,
+    id: Uuid,
+  ) -> HubbitSchemaResult<PersonalAccessToken> {
+    let personal_access_token_repo = context.data_unchecked::<PersonalAccessTokenRepository>();
+    let user = context.data_unchecked::<GammaUser>();
+    let personal_access_token = personal_access_token_repo
+      .revoke(id, user.id)
+      .await
+      .map_err(|e| match e {
+        HubbitError::SqlxError(sqlx::Error::RowNotFound) => HubbitSchemaError::NotFound,
+        e => {
+          error!("[Schema error] {:?}", e);
+          HubbitSchemaError::InternalError
+        }
+      })?;
+    Ok(PersonalAccessToken::from(personal_access_token))
+  }
+}
+
+#[derive(SimpleObject)]
+pub struct PersonalAccessToken {
+  id: Uuid,
+  name: String,
+  scopes: Vec<TokenScope>,
+  expires_at: Option<DateTime<Utc>>,
+  last_used_at: Option<DateTime<Utc>>,
+  revoked_at: Option<DateTime<Utc>>,
+  created_at: DateTime<Utc>,
+}
+
+impl From<models::PersonalAccessToken> for PersonalAccessToken {
+  fn from(personal_access_token: models::PersonalAccessToken) -> Self {
+    Self {
+      id: personal_access_token.id,
+      name: personal_access_token.name,
+      scopes: personal_access_token
+        .scopes
+        .into_iter()
+        .map(TokenScope::from)
+        .collect(),
+      expires_at: personal_access_token.expires_at,
+      last_used_at: personal_access_token.last_used_at,
+      revoked_at: personal_access_token.revoked_at,
+      created_at: personal_access_token.created_at,
+    }
+  }
+}
+
+#[derive(SimpleObject)]
+pub struct CreatedPersonalAccessToken {
+  personal_access_token: PersonalAccessToken,
+  token: String,
+}
+
+/// Tokens without an expiry work until they are revoked
+#[derive(InputObject)]
+pub struct PersonalAccessTokenInput {
+  name: String,
+  scopes: Vec<TokenScope>,
+  expires_at: Option<DateTime<Utc>>,
+}
+
+impl PersonalAccessTokenInput {
+  fn validate(self) -> HubbitSchemaResult<CreatePersonalAccessToken> {
+    let name = self.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 64 || self.scopes.is_empty() {
+      return Err(HubbitSchemaError::InvalidInput);
+    }
+
+    if self
+      .expires_at
+      .map_or(false, |expires_at| expires_at <= Utc::now())
+    {
+      return Err(HubbitSchemaError::InvalidInput);
+    }
+
+    let mut scopes = self.scopes.into_iter().map(i32::from).collect::<Vec<_>>();
+    scopes.sort_unstable();
+    scopes.dedup();
+    Ok(CreatePersonalAccessToken {
+      name,
+      scopes,
+      expires_at: self.expires_at,
+    })
+  }
 }
@@ -1,6 +1,9 @@
+pub mod admin;
 mod device;
+pub mod location;
 pub mod me;
 pub mod session;
+pub mod settings;
 pub mod stats;
 pub mod user;
 
@@ -11,16 +14,23 @@ use async_graphql::{
 };
 use async_trait::async_trait;
 use futures::StreamExt;
+use uuid::Uuid;
 
 use crate::{
-  broker::SimpleBroker, event::UserEvent, models::GammaUser,
+  broker::SimpleBroker,
+  config::Config,
+  event::UserEvent,
+  models::{ApiKey, ApiKeyScope, GammaUser, PersonalAccessToken, TokenScope},
   repositories::user_session::UserSessionRepository,
 };
 
 use self::{
-  device::DeviceMutation,
-  me::MeQuery,
-  session::{ActiveSession, SessionQuery},
+  admin::{AdminMutation, AdminQuery},
+  device::{DeviceMutation, DeviceQuery},
+  location::LocationQuery,
+  me::{MeMutation, MeQuery},
+  session::{unique_by_user, ActiveSession, SessionQuery},
+  settings::SettingsMutation,
   stats::StatsQuery,
   user::{User, UserQuery},
 };
@@ -28,45 +38,58 @@ use self::{
 pub type HubbitSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
 
 #[derive(MergedObject, Default)]
-pub struct QueryRoot(SessionQuery, StatsQuery, MeQuery, UserQuery);
+pub struct QueryRoot(
+  SessionQuery,
+  StatsQuery,
+  MeQuery,
+  UserQuery,
+  LocationQuery,
+  DeviceQuery,
+  AdminQuery,
+);
 
 #[derive(MergedObject, Default)]
-pub struct MutationRoot(DeviceMutation);
+pub struct MutationRoot(DeviceMutation, SettingsMutation, MeMutation, AdminMutation);
 
 #[derive(Default)]
 pub struct SubscriptionRoot;
 
 #[Subscription]
 impl SubscriptionRoot {
-  async fn user_join(&self, context: &Context<'_>) -> impl futures::Stream<Item = ActiveSession> {
+  async fn user_join(
+    &self,
+    context: &Context<'_>,
+    location_id: Option<Uuid>,
+  ) -> impl futures::Stream<Item = ActiveSession> {
     let user_session_repo = context.data_unchecked::<UserSessionRepository>().clone();
+    let timeouts = context.data_unchecked::<Config>().session_timeouts();
     SimpleBroker::<UserEvent>::subscribe().filter_map(move |event| {
       let user_session_repo = user_session_repo.clone();
       async move {
-        if let UserEvent::Join(user_id) = event {
-          match user_session_repo.get_active().await {
-            Ok(active_session) => active_session
-              .into_iter()
-              .find(|session| session.user_id == user_id)
-              .map(|joined_session| ActiveSession {
-                user: User { id: user_id },
-                start_time: joined_session.start_time,
-              }),
-            _ => None,
+        match event {
+          UserEvent::Join(user_id, event_location_id) if event_location_id == location_id => {
+            match user_session_repo.get_active(location_id, timeouts).await {
+              Ok(active_sessions) => unique_by_user(active_sessions)
+                .iter()
+                .find(|session| session.user_id == user_id)
+                .map(ActiveSession::from),
+              _ => None,
+            }
           }
-        } else {
-          None
+          _ => None,
         }
       }
     })
   }
 
-  async fn user_leave(&self) -> impl futures::Stream<Item = User> {
-    SimpleBroker::<UserEvent>::subscribe().filter_map(|event| async move {
-      if let UserEvent::Leave(user_id) = event {
-        Some(User { id: user_id })
-      } else {
-        None
+  async fn user_leave(&self, location_id: Option<Uuid>) -> impl futures::Stream<Item = User> {
+    SimpleBroker::<UserEvent>::subscribe().filter_map(move |event| async move {
+      match event {
+        UserEvent::Leave(user_id, event_location_id) if event_location_id == location_id => {
+          Some(User { id: user_id })
+        }
+        UserEvent::Hide(user_id) => Some(User { id: user_id }),
+        _ => None,
       }
     })
   }
@@ -81,6 +104,9 @@ pub enum HubbitSchemaError {
   InternalError,
   InvalidInput,
   NotFound,
+  InvalidAddress,
+  AddressBelongsToOtherUser,
+  TooManyDevices,
 }
 
 impl ErrorExtensions for HubbitSchemaError {
@@ -90,6 +116,11 @@ impl ErrorExtensions for HubbitSchemaError {
       HubbitSchemaError::NotAuthorized => e.set("code", "NOT_AUTHORIZED"),
       HubbitSchemaError::InvalidInput => e.set("code", "INVALID_INPUT"),
       HubbitSchemaError::NotFound => e.set("code", "NOT_FOUND"),
+      HubbitSchemaError::InvalidAddress => e.set("code", "INVALID_ADDRESS"),
+      HubbitSchemaError::AddressBelongsToOtherUser => {
+        e.set("code", "ADDRESS_BELONGS_TO_OTHER_USER")
+      }
+      HubbitSchemaError::TooManyDevices => e.set("code", "TOO_MANY_DEVICES"),
       _ => (),
     })
   }
@@ -103,19 +134,103 @@ impl Display for HubbitSchemaError {
       HubbitSchemaError::InternalError => write!(f, "Internal unrecoverable error"),
       HubbitSchemaError::InvalidInput => write!(f, "Invalid input"),
       HubbitSchemaError::NotFound => write!(f, "Not found"),
+      HubbitSchemaError::InvalidAddress => write!(f, "Invalid MAC address"),
+      HubbitSchemaError::AddressBelongsToOtherUser => {
+        write!(f, "Address belongs to another user")
+      }
+      HubbitSchemaError::TooManyDevices => write!(f, "Too many devices"),
     }
   }
 }
 
+/// Only lets users logged in through the browser through, personal access
+/// tokens can't be used
 pub struct AuthGuard;
 
 #[async_trait]
 impl Guard for AuthGuard {
   async fn check(&self, context: &Context<'_>) -> Result<()> {
-    if context.data_opt::<GammaUser>().is_some() {
-      Ok(())
+    if context.data_opt::<GammaUser>().is_none() {
+      Err(HubbitSchemaError::NotLoggedIn.extend())
+    } else if context.data_opt::<PersonalAccessToken>().is_some() {
+      Err(HubbitSchemaError::NotAuthorized.extend())
     } else {
+      Ok(())
+    }
+  }
+}
+
+/// Lets logged in users through, as well as personal access tokens with the
+/// scope
+pub struct ScopeGuard {
+  scope: TokenScope,
+}
+
+#[async_trait]
+impl Guard for ScopeGuard {
+  async fn check(&self, context: &Context<'_>) -> Result<()> {
+    if context.data_opt::<GammaUser>().is_none() {
       Err(HubbitSchemaError::NotLoggedIn.extend())
+    } else if !token_allows(context, self.scope) {
+      Err(HubbitSchemaError::NotAuthorized.extend())
+    } else {
+      Ok(())
+    }
+  }
+}
+
+/// Lets logged in users, and API keys or personal access tokens that may read
+/// stats, through
+pub struct ReadGuard;
+
+#[async_trait]
+impl Guard for ReadGuard {
+  async fn check(&self, context: &Context<'_>) -> Result<()> {
+    if context.data_opt::<GammaUser>().is_some() {
+      return if token_allows(context, TokenScope::ReadStats) {
+        Ok(())
+      } else {
+        Err(HubbitSchemaError::NotAuthorized.extend())
+      };
+    }
+
+    match context.data_opt::<ApiKey>() {
+      Some(api_key) if api_key.has_scope(ApiKeyScope::ReadStats) => Ok(()),
+      Some(_) => Err(HubbitSchemaError::NotAuthorized.extend()),
+      None => Err(HubbitSchemaError::NotLoggedIn.extend()),
     }
   }
 }
+
+pub struct AdminGuard;
+
+#[async_trait]
+impl Guard for AdminGuard {
+  async fn check(&self, context: &Context<'_>) -> Result<()> {
+    match context.data_opt::<GammaUser>() {
+      Some(_) if is_admin(context) => Ok(()),
+      Some(_) => Err(HubbitSchemaError::NotAuthorized.extend()),
+      None => Err(HubbitSchemaError::NotLoggedIn.extend()),
+    }
+  }
+}
+
+/// Whether the logged in user is an admin. Personal access tokens never act
+/// as admins, even if their user is one.
+pub fn is_admin(context: &Context<'_>) -> bool {
+  let config = context.data_unchecked::<Config>();
+  context.data_opt::<PersonalAccessToken>().is_none()
+    && context.data_opt::<GammaUser>().map_or(false, |user| {
+      user.is_admin(&config.admin_groups, &config.admin_posts)
+    })
+}
+
+/// Whether the request may do what the scope allows, which is anything unless
+/// it is made with a personal access token
+pub fn token_allows(context: &Context<'_>, scope: TokenScope) -> bool {
+  context
+    .data_opt::<PersonalAccessToken>()
+    .map_or(true, |personal_access_token| {
+      personal_access_token.has_scope(scope)
+    })
+}
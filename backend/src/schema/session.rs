@@ -1,10 +1,18 @@
+use std::collections::HashSet;
+
 use async_graphql::{guard::Guard, Context, Object, SimpleObject};
 use chrono::{DateTime, Utc};
 use log::error;
+use uuid::Uuid;
 
 use crate::{
+  config::Config,
+  models::{PrivacyMode, SessionTimeouts, UserSession},
   repositories::user_session::UserSessionRepository,
-  schema::{user::User, AuthGuard, HubbitSchemaError, HubbitSchemaResult},
+  schema::{
+    location::Location, settings::get_hidden_user_ids, user::User, HubbitSchemaError,
+    HubbitSchemaResult, ReadGuard,
+  },
 };
 
 #[derive(Default)]
@@ -12,32 +20,67 @@ pub struct SessionQuery;
 
 #[Object]
 impl SessionQuery {
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ReadGuard()))]
   pub async fn current_sessions(
     &self,
     context: &Context<'_>,
+    location_id: Option<Uuid>,
   ) -> HubbitSchemaResult<Vec<ActiveSession>> {
     let user_session_repo = context.data_unchecked::<UserSessionRepository>();
-    let active_sessions = user_session_repo.get_active().await.map_err(|e| {
-      error!("[Schema error] {:?}", e);
-      HubbitSchemaError::InternalError
-    })?;
+    let config = context.data_unchecked::<Config>();
+    let mut active_sessions = user_session_repo
+      .get_active(location_id, config.session_timeouts())
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    let hidden_user_ids = get_hidden_user_ids(context, PrivacyMode::Ghost).await?;
+    active_sessions.retain(|session| !hidden_user_ids.contains(&session.user_id));
     Ok(
-      active_sessions
+      unique_by_user(active_sessions)
         .iter()
-        .map(|session| ActiveSession {
-          user: User {
-            id: session.user_id,
-          },
-          start_time: session.start_time,
-        })
+        .map(ActiveSession::from)
         .collect(),
     )
   }
+
+  /// The default timeouts, locations whose reporters override them have their
+  /// own in `Location.sessionTimeouts`
+  #[graphql(guard(ReadGuard()))]
+  pub async fn session_timeouts(&self, context: &Context<'_>) -> SessionTimeouts {
+    let config = context.data_unchecked::<Config>();
+    config.session_timeouts()
+  }
 }
 
 #[derive(SimpleObject)]
 pub struct ActiveSession {
   pub user: User,
   pub start_time: DateTime<Utc>,
+  pub location: Location,
+}
+
+impl From<&UserSession> for ActiveSession {
+  fn from(session: &UserSession) -> Self {
+    Self {
+      user: User {
+        id: session.user_id,
+      },
+      start_time: session.start_time,
+      location: Location {
+        id: session.location_id,
+      },
+    }
+  }
+}
+
+/// Keeps the earliest started session of each user, as a user can be present
+/// in more than one location at a time
+pub fn unique_by_user(mut sessions: Vec<UserSession>) -> Vec<UserSession> {
+  sessions.sort_by_key(|session| session.start_time);
+  let mut seen_user_ids = HashSet::new();
+  sessions.retain(|session| seen_user_ids.insert(session.user_id));
+  sessions.reverse();
+  sessions
 }
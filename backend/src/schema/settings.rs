@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+
+use async_graphql::{guard::Guard, Context, InputObject, MaybeUndefined, Object, SimpleObject};
+use log::error;
+use uuid::Uuid;
+
+use crate::{
+  broker::SimpleBroker,
+  config::Config,
+  event::UserEvent,
+  models::{GammaUser, PrivacyMode, Theme, TokenScope, UserSettings},
+  repositories::{
+    user_session::UserSessionRepository,
+    user_settings::{UpdateUserSettings, UserSettingsRepository},
+  },
+};
+
+use super::{HubbitSchemaError, HubbitSchemaResult, ScopeGuard};
+
+#[derive(SimpleObject)]
+pub struct Settings {
+  pub privacy_mode: PrivacyMode,
+  /// Shown instead of the nick from Gamma, if set
+  pub display_name: Option<String>,
+  pub theme: Theme,
+  pub notify_weekly_summary: bool,
+  pub notify_device_registered: bool,
+}
+
+impl From<UserSettings> for Settings {
+  fn from(settings: UserSettings) -> Self {
+    Self {
+      privacy_mode: PrivacyMode::from(settings.privacy_mode),
+      display_name: settings.display_name,
+      theme: Theme::from(settings.theme),
+      notify_weekly_summary: settings.notify_weekly_summary,
+      notify_device_registered: settings.notify_device_registered,
+    }
+  }
+}
+
+/// Fields that are left out are not changed, `displayName` can be set to
+/// `null` to clear it
+#[derive(InputObject)]
+pub struct UpdateSettingsInput {
+  privacy_mode: Option<PrivacyMode>,
+  display_name: MaybeUndefined<String>,
+  theme: Option<Theme>,
+  notify_weekly_summary: Option<bool>,
+  notify_device_registered: Option<bool>,
+}
+
+#[derive(Default)]
+pub struct SettingsMutation;
+
+#[Object]
+impl SettingsMutation {
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::WriteAccount")))]
+  pub async fn set_privacy_mode(
+    &self,
+    context: &Context<'_>,
+    privacy_mode: PrivacyMode,
+  ) -> HubbitSchemaResult<PrivacyMode> {
+    let settings = update_settings(
+      context,
+      UpdateUserSettings {
+        privacy_mode: Some(privacy_mode),
+        ..Default::default()
+      },
+    )
+    .await?;
+    Ok(settings.privacy_mode)
+  }
+
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::WriteAccount")))]
+  pub async fn update_settings(
+    &self,
+    context: &Context<'_>,
+    data: UpdateSettingsInput,
+  ) -> HubbitSchemaResult<Settings> {
+    let display_name = match data.display_name {
+      MaybeUndefined::Undefined => None,
+      MaybeUndefined::Null => Some(None),
+      MaybeUndefined::Value(display_name) => {
+        let display_name = display_name.trim();
+        if display_name.is_empty() || display_name.chars().count() > 32 {
+          return Err(HubbitSchemaError::InvalidInput);
+        }
+        Some(Some(display_name.to_owned()))
+      }
+    };
+
+    update_settings(
+      context,
+      UpdateUserSettings {
+        privacy_mode: data.privacy_mode,
+        display_name,
+        theme: data.theme,
+        notify_weekly_summary: data.notify_weekly_summary,
+        notify_device_registered: data.notify_device_registered,
+      },
+    )
+    .await
+  }
+}
+
+pub async fn get_settings(context: &Context<'_>, user_id: Uuid) -> HubbitSchemaResult<Settings> {
+  let user_settings_repo = context.data_unchecked::<UserSettingsRepository>();
+  let settings = user_settings_repo
+    .get_for_user(user_id)
+    .await
+    .map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+  Ok(match settings {
+    Some(settings) => Settings::from(settings),
+    None => Settings {
+      privacy_mode: PrivacyMode::Visible,
+      display_name: None,
+      theme: Theme::System,
+      notify_weekly_summary: false,
+      notify_device_registered: true,
+    },
+  })
+}
+
+async fn update_settings(
+  context: &Context<'_>,
+  data: UpdateUserSettings,
+) -> HubbitSchemaResult<Settings> {
+  let user_settings_repo = context.data_unchecked::<UserSettingsRepository>();
+  let auth_user = context.data_unchecked::<GammaUser>();
+  let previous_privacy_mode = get_privacy_mode(context, auth_user.id).await?;
+  let settings = user_settings_repo
+    .update(auth_user.id, data)
+    .await
+    .map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+  let privacy_mode = PrivacyMode::from(settings.privacy_mode);
+
+  // Make the user disappear from, or reappear in, the lists of present users
+  // that are kept by subscribers
+  if previous_privacy_mode == PrivacyMode::Visible && privacy_mode != PrivacyMode::Visible {
+    SimpleBroker::publish(UserEvent::Hide(auth_user.id));
+  } else if previous_privacy_mode != PrivacyMode::Visible && privacy_mode == PrivacyMode::Visible {
+    let user_session_repo = context.data_unchecked::<UserSessionRepository>();
+    let config = context.data_unchecked::<Config>();
+    let active_sessions = user_session_repo
+      .get_active(None, config.session_timeouts())
+      .await
+      .map_err(|e| {
+        error!("[Schema error] {:?}", e);
+        HubbitSchemaError::InternalError
+      })?;
+    let mut location_ids = active_sessions
+      .iter()
+      .filter(|session| session.user_id == auth_user.id)
+      .map(|session| session.location_id)
+      .collect::<Vec<_>>();
+    location_ids.sort_unstable();
+    location_ids.dedup();
+    if !location_ids.is_empty() {
+      SimpleBroker::publish(UserEvent::Join(auth_user.id, None));
+    }
+    for location_id in location_ids {
+      SimpleBroker::publish(UserEvent::Join(auth_user.id, Some(location_id)));
+    }
+  }
+
+  Ok(Settings::from(settings))
+}
+
+pub async fn get_privacy_mode(
+  context: &Context<'_>,
+  user_id: Uuid,
+) -> HubbitSchemaResult<PrivacyMode> {
+  let user_settings_repo = context.data_unchecked::<UserSettingsRepository>();
+  let settings = user_settings_repo
+    .get_for_user(user_id)
+    .await
+    .map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+  Ok(
+    settings
+      .map(|settings| PrivacyMode::from(settings.privacy_mode))
+      .unwrap_or(PrivacyMode::Visible),
+  )
+}
+
+/// Returns the users whose privacy mode is at least as strict as `privacy_mode`
+pub async fn get_hidden_user_ids(
+  context: &Context<'_>,
+  privacy_mode: PrivacyMode,
+) -> HubbitSchemaResult<HashSet<Uuid>> {
+  let user_settings_repo = context.data_unchecked::<UserSettingsRepository>();
+  let user_ids = user_settings_repo
+    .get_user_ids_with_privacy_mode(privacy_mode)
+    .await
+    .map_err(|e| {
+      error!("[Schema error] {:?}", e);
+      HubbitSchemaError::InternalError
+    })?;
+  Ok(user_ids.into_iter().collect())
+}
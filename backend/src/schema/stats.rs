@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use async_graphql::{guard::Guard, Context, InputObject, Object, SimpleObject};
 use chrono::{Datelike, Duration, TimeZone, Utc, Weekday};
@@ -7,9 +7,9 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-  models::Period,
+  models::{Period, PrivacyMode},
   repositories::{study_period::StudyPeriodRepository, study_year::StudyYearRepository},
-  schema::{AuthGuard, HubbitSchemaError, HubbitSchemaResult},
+  schema::{settings::get_hidden_user_ids, HubbitSchemaError, HubbitSchemaResult, ReadGuard},
   services::{
     stats::{Stat as ServiceStat, StatsService},
     user::UserService,
@@ -119,24 +119,30 @@ pub struct StatsQuery;
 
 #[Object]
 impl StatsQuery {
-  #[graphql(guard(AuthGuard()))]
-  pub async fn stats_alltime(&self, context: &Context<'_>) -> HubbitSchemaResult<Vec<Stat>> {
+  #[graphql(guard(ReadGuard()))]
+  pub async fn stats_alltime(
+    &self,
+    context: &Context<'_>,
+    location_id: Option<Uuid>,
+  ) -> HubbitSchemaResult<Vec<Stat>> {
     let stats_service = context.data_unchecked::<StatsService>();
-    let stats = stats_service.get_alltime().await.map_err(|e| {
+    let stats = stats_service.get_alltime(location_id).await.map_err(|e| {
       error!("[Schema error] {:?}", e);
       HubbitSchemaError::InternalError
     })?;
 
+    let hidden_user_ids = get_hidden_user_ids(context, PrivacyMode::Hidden).await?;
     prefetch_users(context, &stats).await?;
 
-    Ok(sort_and_map_stats(stats, &None))
+    Ok(sort_and_map_stats(stats, &None, &hidden_user_ids))
   }
 
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ReadGuard()))]
   pub async fn stats_study_year(
     &self,
     context: &Context<'_>,
     input: Option<StatsStudyYearInput>,
+    location_id: Option<Uuid>,
   ) -> HubbitSchemaResult<StatsStudyYearPayload> {
     let year = if let Some(input) = input {
       input.year
@@ -153,7 +159,7 @@ impl StatsQuery {
     };
 
     let stats_service = context.data_unchecked::<StatsService>();
-    let stats = match stats_service.get_study_year(year).await {
+    let stats = match stats_service.get_study_year(year, location_id).await {
       Ok(stats) => stats,
       Err(HubbitError::SqlxError(Error::RowNotFound)) => {
         return Ok(StatsStudyYearPayload {
@@ -173,22 +179,27 @@ impl StatsQuery {
       .field("prevPosition")
       .exists()
     {
-      stats_service.get_study_year(year - 1).await.ok()
+      stats_service
+        .get_study_year(year - 1, location_id)
+        .await
+        .ok()
     } else {
       None
     };
 
+    let hidden_user_ids = get_hidden_user_ids(context, PrivacyMode::Hidden).await?;
     prefetch_users(context, &stats).await?;
 
-    let stats = sort_and_map_stats(stats, &previous_stats);
+    let stats = sort_and_map_stats(stats, &previous_stats, &hidden_user_ids);
     Ok(StatsStudyYearPayload { stats, year })
   }
 
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ReadGuard()))]
   pub async fn stats_study_period(
     &self,
     context: &Context<'_>,
     input: Option<StatsStudyPeriodInput>,
+    location_id: Option<Uuid>,
   ) -> HubbitSchemaResult<StatsStudyPeriodPayload> {
     let (year, period) = if let Some(input) = input {
       (input.year, input.period)
@@ -202,7 +213,10 @@ impl StatsQuery {
     };
 
     let stats_service = context.data_unchecked::<StatsService>();
-    let stats = match stats_service.get_study_period(year, period).await {
+    let stats = match stats_service
+      .get_study_period(year, period, location_id)
+      .await
+    {
       Ok(stats) => stats,
       Err(HubbitError::SqlxError(Error::RowNotFound)) => {
         return Ok(StatsStudyPeriodPayload {
@@ -231,16 +245,17 @@ impl StatsQuery {
         Period::LP4 => (year, Period::LP3),
       };
       stats_service
-        .get_study_period(prev_year, prev_period)
+        .get_study_period(prev_year, prev_period, location_id)
         .await
         .ok()
     } else {
       None
     };
 
+    let hidden_user_ids = get_hidden_user_ids(context, PrivacyMode::Hidden).await?;
     prefetch_users(context, &stats).await?;
 
-    let stats = sort_and_map_stats(stats, &previous_stats);
+    let stats = sort_and_map_stats(stats, &previous_stats, &hidden_user_ids);
     Ok(StatsStudyPeriodPayload {
       stats,
       year,
@@ -248,22 +263,23 @@ impl StatsQuery {
     })
   }
 
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ReadGuard()))]
   pub async fn stats_month(
     &self,
     context: &Context<'_>,
     input: Option<StatsMonthInput>,
+    location_id: Option<Uuid>,
   ) -> HubbitSchemaResult<StatsMonthPayload> {
     let (year, month) = if let Some(input) = input {
       (input.year, input.month)
     } else {
       let now = Utc::now();
-      (now.year() as i32, now.month() as i32)
+      (now.year(), now.month() as i32)
     };
 
     let stats_service = context.data_unchecked::<StatsService>();
     let stats = stats_service
-      .get_month(year, month as u32)
+      .get_month(year, month as u32, location_id)
       .await
       .map_err(|e| {
         error!("[Schema error] {:?}", e);
@@ -282,16 +298,17 @@ impl StatsQuery {
 
     let previous_stats = if context.look_ahead().field("prevPosition").exists() {
       stats_service
-        .get_month(prev_year, prev_month as u32)
+        .get_month(prev_year, prev_month as u32, location_id)
         .await
         .ok()
     } else {
       None
     };
 
+    let hidden_user_ids = get_hidden_user_ids(context, PrivacyMode::Hidden).await?;
     prefetch_users(context, &stats).await?;
 
-    let stats = sort_and_map_stats(stats, &previous_stats);
+    let stats = sort_and_map_stats(stats, &previous_stats, &hidden_user_ids);
     Ok(StatsMonthPayload {
       stats,
       curr: YearMonth { year, month },
@@ -306,22 +323,23 @@ impl StatsQuery {
     })
   }
 
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ReadGuard()))]
   pub async fn stats_week(
     &self,
     context: &Context<'_>,
     input: Option<StatsWeekInput>,
+    location_id: Option<Uuid>,
   ) -> HubbitSchemaResult<StatsWeekPayload> {
     let (year, week) = if let Some(input) = input {
       (input.year, input.week)
     } else {
       let now = Utc::now();
-      (now.year() as i32, now.iso_week().week() as i32)
+      (now.year(), now.iso_week().week() as i32)
     };
 
     let stats_service = context.data_unchecked::<StatsService>();
     let stats = stats_service
-      .get_week(year, week as u32)
+      .get_week(year, week as u32, location_id)
       .await
       .map_err(|e| {
         error!("[Schema error] {:?}", e);
@@ -334,16 +352,17 @@ impl StatsQuery {
 
     let previous_stats = if context.look_ahead().field("prevPosition").exists() {
       stats_service
-        .get_week(prev_week.year(), prev_week.iso_week().week() as u32)
+        .get_week(prev_week.year(), prev_week.iso_week().week(), location_id)
         .await
         .ok()
     } else {
       None
     };
 
+    let hidden_user_ids = get_hidden_user_ids(context, PrivacyMode::Hidden).await?;
     prefetch_users(context, &stats).await?;
 
-    let stats = sort_and_map_stats(stats, &previous_stats);
+    let stats = sort_and_map_stats(stats, &previous_stats, &hidden_user_ids);
     Ok(StatsWeekPayload {
       stats,
       curr: YearWeek { year, week },
@@ -358,22 +377,23 @@ impl StatsQuery {
     })
   }
 
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ReadGuard()))]
   pub async fn stats_day(
     &self,
     context: &Context<'_>,
     input: Option<StatsDayInput>,
+    location_id: Option<Uuid>,
   ) -> HubbitSchemaResult<StatsDayPayload> {
     let (year, month, day) = if let Some(input) = input {
       (input.year, input.month, input.day)
     } else {
       let now = Utc::now();
-      (now.year() as i32, now.month() as i32, now.day() as i32)
+      (now.year(), now.month() as i32, now.day() as i32)
     };
 
     let stats_service = context.data_unchecked::<StatsService>();
     let stats = stats_service
-      .get_day(year, month as u32, day as u32)
+      .get_day(year, month as u32, day as u32, location_id)
       .await
       .map_err(|e| {
         error!("[Schema error] {:?}", e);
@@ -386,16 +406,22 @@ impl StatsQuery {
 
     let previous_stats = if context.look_ahead().field("prevPosition").exists() {
       stats_service
-        .get_day(prev_day.year(), prev_day.month(), prev_day.day())
+        .get_day(
+          prev_day.year(),
+          prev_day.month(),
+          prev_day.day(),
+          location_id,
+        )
         .await
         .ok()
     } else {
       None
     };
 
+    let hidden_user_ids = get_hidden_user_ids(context, PrivacyMode::Hidden).await?;
     prefetch_users(context, &stats).await?;
 
-    let stats = sort_and_map_stats(stats, &previous_stats);
+    let stats = sort_and_map_stats(stats, &previous_stats, &hidden_user_ids);
     Ok(StatsDayPayload {
       stats,
       curr: YearMonthDay { year, month, day },
@@ -435,13 +461,16 @@ async fn prefetch_users(
   Ok(())
 }
 
+// Hidden users are left out, without leaving gaps in the positions
 fn sort_and_map_stats(
   stats: HashMap<Uuid, ServiceStat>,
   prev_stats: &Option<HashMap<Uuid, ServiceStat>>,
+  hidden_user_ids: &HashSet<Uuid>,
 ) -> Vec<Stat> {
   let prev_positions = if let Some(prev_stats) = prev_stats {
     let mut prev_stats = prev_stats
       .iter()
+      .filter(|(user_id, _)| !hidden_user_ids.contains(user_id))
       .map(|(user_id, stat)| (*user_id, stat.duration_ms))
       .collect::<Vec<_>>();
     prev_stats.sort_by_key(|(_, dur)| -dur);
@@ -454,7 +483,10 @@ fn sort_and_map_stats(
     HashMap::new()
   };
 
-  let mut stats = stats.into_iter().map(|(_, stat)| stat).collect::<Vec<_>>();
+  let mut stats = stats
+    .into_values()
+    .filter(|stat| !hidden_user_ids.contains(&stat.user_id))
+    .collect::<Vec<_>>();
   stats.sort_by_key(|stat| -stat.duration_ms);
   stats
     .iter()
@@ -463,7 +495,7 @@ fn sort_and_map_stats(
       user: User { id: stat.user_id },
       duration_seconds: stat.duration_ms / 1000,
       current_position: index as i32 + 1,
-      prev_position: prev_positions.get(&stat.user_id).map(|&v| v as i32),
+      prev_position: prev_positions.get(&stat.user_id).copied(),
     })
     .collect()
 }
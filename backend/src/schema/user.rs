@@ -5,13 +5,18 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-  models::{GammaUser, UserSession},
+  models::{GammaUser, PrivacyMode, TokenScope, UserSession},
   repositories::{device::DeviceRepository, user_session::UserSessionRepository},
   services::{hour_stats::HourStatsService, user::UserService},
   utils::{MAX_DATETIME, MIN_DATETIME},
 };
 
-use super::{device::Device, AuthGuard, HubbitSchemaError, HubbitSchemaResult};
+use super::{
+  device::Device,
+  is_admin,
+  settings::{get_privacy_mode, get_settings},
+  token_allows, HubbitSchemaError, HubbitSchemaResult, ScopeGuard,
+};
 
 #[derive(Default)]
 pub struct UserQuery;
@@ -24,7 +29,7 @@ pub struct UserUniqueInput {
 
 #[Object]
 impl UserQuery {
-  #[graphql(guard(AuthGuard()))]
+  #[graphql(guard(ScopeGuard(scope = "TokenScope::ReadStats")))]
   pub async fn user(
     &self,
     context: &Context<'_>,
@@ -122,26 +127,23 @@ impl User {
 
   async fn hour_stats(&self, context: &Context<'_>) -> HubbitSchemaResult<Vec<u32>> {
     let hour_stats_service = context.data_unchecked::<HourStatsService>();
+    let include_ongoing = self.shows_ongoing_sessions(context).await?;
     Ok(
       hour_stats_service
-        .get_for_user(self.id)
+        .get_for_user(self.id, include_ongoing)
         .await
         .map_err(|_| HubbitSchemaError::InternalError)?,
     )
   }
 
   async fn recent_sessions(&self, context: &Context<'_>) -> HubbitSchemaResult<Vec<Session>> {
-    let user_session_repo = context.data_unchecked::<UserSessionRepository>();
-    let sessions = user_session_repo
-      .get_range_for_user(*MIN_DATETIME, *MAX_DATETIME, self.id)
-      .await
-      .map_err(|_| HubbitSchemaError::InternalError)?;
+    let sessions = self.get_visible_sessions(context).await?;
     Ok(
       sessions
         .iter()
         .map(|session| Session {
           start_time: session.start_time,
-          end_time: session.end_time,
+          end_time: session.effective_end_time(),
         })
         .take(10)
         .collect(),
@@ -149,16 +151,12 @@ impl User {
   }
 
   async fn longest_session(&self, context: &Context<'_>) -> HubbitSchemaResult<Option<Session>> {
-    let user_session_repo = context.data_unchecked::<UserSessionRepository>();
-    let sessions = user_session_repo
-      .get_range_for_user(*MIN_DATETIME, *MAX_DATETIME, self.id)
-      .await
-      .map_err(|_| HubbitSchemaError::InternalError)?;
+    let sessions = self.get_visible_sessions(context).await?;
     let mut longest_session: Option<UserSession> = None;
     for session in sessions {
       if let Some(longest_session_inner) = &longest_session {
-        if session.end_time - session.start_time
-          > longest_session_inner.end_time - longest_session_inner.start_time
+        if session.effective_end_time() - session.start_time
+          > longest_session_inner.effective_end_time() - longest_session_inner.start_time
         {
           longest_session = Some(session);
         }
@@ -169,29 +167,53 @@ impl User {
 
     Ok(longest_session.map(|session| Session {
       start_time: session.start_time,
-      end_time: session.end_time,
+      end_time: session.effective_end_time(),
     }))
   }
 
   async fn total_time_seconds(&self, context: &Context<'_>) -> HubbitSchemaResult<i64> {
-    let user_session_repo = context.data_unchecked::<UserSessionRepository>();
-    let sessions = user_session_repo
-      .get_range_for_user(*MIN_DATETIME, *MAX_DATETIME, self.id)
-      .await
-      .map_err(|_| HubbitSchemaError::InternalError)?;
+    let sessions = self.get_visible_sessions(context).await?;
 
     let duration_ms = sessions.iter().fold(0, |prev, cur| {
-      prev + (cur.end_time - cur.start_time).num_milliseconds()
+      prev + (cur.effective_end_time() - cur.start_time).num_milliseconds()
     });
 
     Ok(duration_ms / 1000)
   }
 
+  async fn display_name(&self, context: &Context<'_>) -> HubbitSchemaResult<Option<String>> {
+    Ok(get_settings(context, self.id).await?.display_name)
+  }
+
+  async fn privacy_mode(&self, context: &Context<'_>) -> HubbitSchemaResult<PrivacyMode> {
+    let auth_user = context
+      .data::<GammaUser>()
+      .map_err(|_| HubbitSchemaError::NotLoggedIn)?;
+    if self.id != auth_user.id || !token_allows(context, TokenScope::ReadAccount) {
+      return Err(HubbitSchemaError::NotAuthorized);
+    }
+
+    get_privacy_mode(context, self.id).await
+  }
+
+  async fn is_admin(&self, context: &Context<'_>) -> HubbitSchemaResult<bool> {
+    let auth_user = context
+      .data::<GammaUser>()
+      .map_err(|_| HubbitSchemaError::NotLoggedIn)?;
+    if self.id != auth_user.id || !token_allows(context, TokenScope::ReadAccount) {
+      return Err(HubbitSchemaError::NotAuthorized);
+    }
+
+    Ok(is_admin(context))
+  }
+
+  /// Only readable by the user, and by admins
   pub async fn devices(&self, context: &Context<'_>) -> HubbitSchemaResult<Vec<Device>> {
     let auth_user = context
       .data::<GammaUser>()
       .map_err(|_| HubbitSchemaError::NotLoggedIn)?;
-    if self.id != auth_user.id {
+    let is_own = self.id == auth_user.id && token_allows(context, TokenScope::ReadAccount);
+    if !is_own && !is_admin(context) {
       return Err(HubbitSchemaError::NotAuthorized);
     }
 
@@ -209,6 +231,36 @@ impl User {
   }
 }
 
+impl User {
+  // Ongoing sessions of users in ghost mode, or stricter, would reveal that
+  // they are present, so they are only shown to the users themselves
+  async fn shows_ongoing_sessions(&self, context: &Context<'_>) -> HubbitSchemaResult<bool> {
+    let is_self = context
+      .data::<GammaUser>()
+      .map(|auth_user| auth_user.id == self.id)
+      .unwrap_or(false);
+    Ok(is_self || get_privacy_mode(context, self.id).await? == PrivacyMode::Visible)
+  }
+
+  async fn get_visible_sessions(
+    &self,
+    context: &Context<'_>,
+  ) -> HubbitSchemaResult<Vec<UserSession>> {
+    let user_session_repo = context.data_unchecked::<UserSessionRepository>();
+    let mut sessions = user_session_repo
+      .get_range_for_user(*MIN_DATETIME, *MAX_DATETIME, self.id)
+      .await
+      .map_err(|_| HubbitSchemaError::InternalError)?;
+
+    if !self.shows_ongoing_sessions(context).await? {
+      let now = Utc::now();
+      sessions.retain(|session| session.expires_at <= now);
+    }
+
+    Ok(sessions)
+  }
+}
+
 #[derive(SimpleObject)]
 pub struct Session {
   start_time: DateTime<Utc>,
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+  config::Config,
+  error::{HubbitError, HubbitResult},
+  identity::DynIdentityProvider,
+  models::GammaUser,
+  services::util::{redis_del, redis_get, redis_sadd, redis_set_ex, redis_smembers, redis_srem},
+  utils::token::{generate_token, hash_token},
+  RedisPool,
+};
+
+// How long a validated token is still trusted while Gamma can't be reached
+const MAX_STALE_MINUTES: i64 = 60;
+
+struct TokenEntry {
+  user: GammaUser,
+  validated_at: DateTime<Utc>,
+}
+
+/// A login, stored in Redis so that it can be revoked. The cookie only holds
+/// the id of the session.
+#[derive(Debug, Deserialize, Serialize)]
+struct LoginSession {
+  user_id: Uuid,
+  access_token: String,
+  expires_at: DateTime<Utc>,
+}
+
+type TokenCache = HashMap<String, Arc<Mutex<Option<TokenEntry>>>>;
+
+/// Keeps track of login sessions, and resolves Gamma access tokens to users,
+/// only asking Gamma about tokens that haven't been validated within the TTL.
+/// Tokens are kept hashed in the cache.
+#[derive(Clone)]
+pub struct AuthService {
+  config: Config,
+  identity_provider: DynIdentityProvider,
+  ttl: Duration,
+  cache: Arc<Mutex<TokenCache>>,
+  redis_pool: RedisPool,
+}
+
+impl AuthService {
+  pub fn new(
+    config: Config,
+    identity_provider: DynIdentityProvider,
+    redis_pool: RedisPool,
+  ) -> Self {
+    let ttl = Duration::seconds(config.token_cache_seconds);
+    Self {
+      config,
+      identity_provider,
+      ttl,
+      cache: Arc::new(Mutex::new(HashMap::new())),
+      redis_pool,
+    }
+  }
+
+  /// Starts a session for the user of the access token, which lasts as long
+  /// as the token does. Returns the id of the session.
+  pub async fn create_session(
+    &self,
+    access_token: String,
+    expires_in_seconds: Option<i64>,
+  ) -> HubbitResult<String> {
+    let user = self.get_user(&access_token).await?;
+    let lifetime_seconds = expires_in_seconds
+      .filter(|&seconds| seconds > 0)
+      .unwrap_or(self.config.login_session_hours * 60 * 60);
+    let session_id = generate_token();
+    let login_session = LoginSession {
+      user_id: user.id,
+      access_token,
+      expires_at: Utc::now() + Duration::seconds(lifetime_seconds),
+    };
+    redis_set_ex(
+      self.redis_pool.clone(),
+      session_key(&session_id),
+      login_session,
+      lifetime_seconds as usize,
+    )
+    .await?;
+    // The index of the sessions of the user expires along with the one that
+    // lasts the longest
+    redis_sadd(
+      self.redis_pool.clone(),
+      user_sessions_key(user.id),
+      session_id.clone(),
+      lifetime_seconds as usize,
+    )
+    .await?;
+    Ok(session_id)
+  }
+
+  /// Errors if the session doesn't exist, has expired or if Gamma no longer
+  /// accepts its token, in which case the session is ended
+  pub async fn get_session_user(&self, session_id: &str) -> HubbitResult<GammaUser> {
+    let login_session =
+      redis_get::<LoginSession>(self.redis_pool.clone(), &session_key(session_id)).await?;
+    if login_session.expires_at <= Utc::now() {
+      return Err(HubbitError::NotFound);
+    }
+
+    match self.get_user(&login_session.access_token).await {
+      Ok(user) => Ok(user),
+      Err(e) if is_unavailable(&e) => Err(e),
+      Err(e) => {
+        self.delete_session(session_id).await?;
+        Err(e)
+      }
+    }
+  }
+
+  pub async fn delete_session(&self, session_id: &str) -> HubbitResult<()> {
+    let key = session_key(session_id);
+    if let Ok(login_session) = redis_get::<LoginSession>(self.redis_pool.clone(), &key).await {
+      self.invalidate(&login_session.access_token).await;
+      redis_srem(
+        self.redis_pool.clone(),
+        user_sessions_key(login_session.user_id),
+        session_id.to_owned(),
+      )
+      .await?;
+    }
+    redis_del(self.redis_pool.clone(), &[key]).await
+  }
+
+  /// Ends every session of the user, on all devices
+  pub async fn delete_user_sessions(&self, user_id: Uuid) -> HubbitResult<()> {
+    let user_sessions_key = user_sessions_key(user_id);
+    let mut keys = redis_smembers(self.redis_pool.clone(), &user_sessions_key)
+      .await?
+      .iter()
+      .map(|session_id| session_key(session_id))
+      .collect::<Vec<_>>();
+    keys.push(user_sessions_key);
+    redis_del(self.redis_pool.clone(), &keys).await?;
+    self.invalidate_user(user_id).await;
+    Ok(())
+  }
+
+  pub async fn get_user(&self, access_token: &str) -> HubbitResult<GammaUser> {
+    let entry_lock = self.get_entry_lock(hash_token(access_token)).await;
+    // Concurrent requests with the same token wait here, so that only one of
+    // them asks Gamma
+    let mut entry = entry_lock.lock().await;
+    let now = Utc::now();
+    if let Some(cached) = &*entry {
+      if now < cached.validated_at + self.ttl {
+        return Ok(cached.user.clone());
+      }
+    }
+
+    match self.identity_provider.get_current_user(access_token).await {
+      Ok(user) => {
+        *entry = Some(TokenEntry {
+          user: user.clone(),
+          validated_at: now,
+        });
+        Ok(user)
+      }
+      Err(e) if is_unavailable(&e) => match &*entry {
+        Some(cached) if now < cached.validated_at + Duration::minutes(MAX_STALE_MINUTES) => {
+          warn!("[Auth] Gamma unavailable, using previously validated token");
+          Ok(cached.user.clone())
+        }
+        _ => Err(e),
+      },
+      Err(e) => {
+        *entry = None;
+        Err(e)
+      }
+    }
+  }
+
+  pub async fn invalidate(&self, access_token: &str) {
+    self.cache.lock().await.remove(&hash_token(access_token));
+  }
+
+  /// Forgets every token of the user, e.g. when their permissions change.
+  /// Entries that are being validated may belong to the user, so they are
+  /// forgotten too, and validated again when they are next used.
+  pub async fn invalidate_user(&self, user_id: Uuid) {
+    self
+      .cache
+      .lock()
+      .await
+      .retain(|_, entry| match entry.try_lock() {
+        Ok(entry) => entry
+          .as_ref()
+          .map_or(true, |cached| cached.user.id != user_id),
+        Err(_) => false,
+      });
+  }
+
+  /// Removes tokens that can no longer be used without asking Gamma, entries
+  /// that are being validated are left alone
+  pub async fn prune_expired(&self) {
+    let stale_before = Utc::now() - Duration::minutes(MAX_STALE_MINUTES);
+    self
+      .cache
+      .lock()
+      .await
+      .retain(|_, entry| match entry.try_lock() {
+        Ok(entry) => entry
+          .as_ref()
+          .map_or(false, |cached| cached.validated_at > stale_before),
+        Err(_) => true,
+      });
+  }
+
+  async fn get_entry_lock(&self, token_hash: String) -> Arc<Mutex<Option<TokenEntry>>> {
+    self
+      .cache
+      .lock()
+      .await
+      .entry(token_hash)
+      .or_insert_with(|| Arc::new(Mutex::new(None)))
+      .clone()
+  }
+}
+
+fn session_key(session_id: &str) -> String {
+  format!("login_session:{}", session_id)
+}
+
+fn user_sessions_key(user_id: Uuid) -> String {
+  format!("login_sessions:user:{}", user_id)
+}
+
+// Gamma being down, or not answering, is not the same as rejecting the token
+fn is_unavailable(e: &HubbitError) -> bool {
+  match e {
+    HubbitError::ReqwestError(e) => match e.status() {
+      Some(status) => status.is_server_error(),
+      None => true,
+    },
+    _ => false,
+  }
+}
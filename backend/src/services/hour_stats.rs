@@ -1,4 +1,4 @@
-use chrono::Timelike;
+use chrono::{Timelike, Utc};
 use uuid::Uuid;
 
 use crate::{
@@ -17,11 +17,17 @@ impl HourStatsService {
     Self { user_session_repo }
   }
 
-  pub async fn get_for_user(&self, user_id: Uuid) -> HubbitResult<Vec<u32>> {
-    let user_sessions = self
+  /// Minutes spent in each hour of the day, leaving out the ongoing session
+  /// unless `include_ongoing` is set
+  pub async fn get_for_user(&self, user_id: Uuid, include_ongoing: bool) -> HubbitResult<Vec<u32>> {
+    let mut user_sessions = self
       .user_session_repo
       .get_range_for_user(*MIN_DATETIME, *MAX_DATETIME, user_id)
       .await?;
+    if !include_ongoing {
+      let now = Utc::now();
+      user_sessions.retain(|session| session.expires_at <= now);
+    }
 
     let hour_stats = calculate_hour_stats(&user_sessions);
 
@@ -34,15 +40,15 @@ fn calculate_hour_stats(sessions: &[UserSession]) -> Vec<u32> {
 
   for session in sessions {
     let start_hour = session.start_time.hour();
+    let end_time = session.effective_end_time();
 
     // If session is within an hour, only get minute diff
-    if start_hour == session.end_time.hour() {
-      hour_stats[start_hour as usize] +=
-        (session.end_time - session.start_time).num_minutes() as u32;
+    if start_hour == end_time.hour() {
+      hour_stats[start_hour as usize] += (end_time - session.start_time).num_minutes() as u32;
       continue;
     }
 
-    let end_hour = session.end_time.hour();
+    let end_hour = end_time.hour();
     let middle_hours = start_hour + 1..end_hour;
 
     hour_stats[start_hour as usize] += 59 - session.start_time.minute();
@@ -50,7 +56,7 @@ fn calculate_hour_stats(sessions: &[UserSession]) -> Vec<u32> {
       hour_stats[hour as usize] += 60;
     }
 
-    hour_stats[end_hour as usize] += session.end_time.minute() + 1;
+    hour_stats[end_hour as usize] += end_time.minute() + 1;
   }
 
   hour_stats
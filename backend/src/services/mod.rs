@@ -1,4 +1,6 @@
+pub mod auth;
 pub mod hour_stats;
+pub mod report_signature;
 pub mod stats;
 pub mod user;
 mod util;
@@ -0,0 +1,102 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{error::HubbitError, services::util::redis_set_nx_ex, RedisPool};
+
+// Signed reports are accepted this long before or after they were signed
+const MAX_SIGNATURE_AGE_SECONDS: i64 = 5 * 60;
+
+/// What a reporter sends along with a report made with a key that has a signing
+/// secret
+pub struct ReportSignature {
+  /// Unix time in seconds of when the report was signed
+  pub timestamp: i64,
+  /// Unique per report, between 16 and 64 alphanumeric characters
+  pub nonce: String,
+  /// Hex encoded HMAC-SHA256 of `"{timestamp}\n{nonce}\n"` followed by the
+  /// body, keyed with the signing secret
+  pub signature: String,
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+  Expired,
+  InvalidNonce,
+  InvalidSignature,
+  Replayed,
+  Internal(HubbitError),
+}
+
+impl From<HubbitError> for SignatureError {
+  fn from(e: HubbitError) -> Self {
+    SignatureError::Internal(e)
+  }
+}
+
+#[derive(Clone)]
+pub struct ReportSignatureService {
+  redis_pool: RedisPool,
+}
+
+impl ReportSignatureService {
+  pub fn new(redis_pool: RedisPool) -> Self {
+    Self { redis_pool }
+  }
+
+  /// Checks that the report was signed recently with the secret, and that the
+  /// nonce hasn't been used by the key before
+  pub async fn verify(
+    &self,
+    api_key_id: Uuid,
+    signing_secret: &str,
+    signature: &ReportSignature,
+    body: &[u8],
+  ) -> Result<(), SignatureError> {
+    // The timestamp is sent by the reporter, so it may be anything
+    let now = Utc::now().timestamp();
+    if signature.timestamp < now - MAX_SIGNATURE_AGE_SECONDS
+      || signature.timestamp > now + MAX_SIGNATURE_AGE_SECONDS
+    {
+      return Err(SignatureError::Expired);
+    }
+
+    let nonce = &signature.nonce;
+    if !(16..=64).contains(&nonce.len()) || !nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+      return Err(SignatureError::InvalidNonce);
+    }
+
+    let is_valid = match hex::decode(&signature.signature) {
+      Ok(tag) => report_hmac(signing_secret, signature.timestamp, nonce, body)
+        .verify(&tag)
+        .is_ok(),
+      Err(_) => false,
+    };
+    if !is_valid {
+      return Err(SignatureError::InvalidSignature);
+    }
+
+    // Nonces only have to be remembered for as long as their timestamp would
+    // be accepted
+    let is_new_nonce = redis_set_nx_ex(
+      self.redis_pool.clone(),
+      format!("report_nonce:{}:{}", api_key_id, nonce),
+      (2 * MAX_SIGNATURE_AGE_SECONDS) as usize,
+    )
+    .await?;
+    if !is_new_nonce {
+      return Err(SignatureError::Replayed);
+    }
+
+    Ok(())
+  }
+}
+
+fn report_hmac(signing_secret: &str, timestamp: i64, nonce: &str, body: &[u8]) -> Hmac<Sha256> {
+  let mut hmac =
+    Hmac::<Sha256>::new_varkey(signing_secret.as_bytes()).expect("HMAC can take a key of any size");
+  hmac.update(format!("{}\n{}\n", timestamp, nonce).as_bytes());
+  hmac.update(body);
+  hmac
+}
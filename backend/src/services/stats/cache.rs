@@ -2,12 +2,13 @@ use std::collections::HashMap;
 
 use async_graphql::futures_util::future::join_all;
 use chrono::{Datelike, Duration, Local, NaiveDate, TimeZone};
+use uuid::Uuid;
 
 use crate::{
   error::HubbitResult,
   services::{
     stats::util::{day_time_bounds, month_time_bounds, year_time_bounds},
-    util::{redis_get, redis_set},
+    util::{redis_del, redis_get, redis_set},
   },
 };
 
@@ -18,6 +19,7 @@ impl StatsService {
     &self,
     mut start_date: NaiveDate,
     mut end_date: NaiveDate,
+    location_id: Option<Uuid>,
   ) -> HubbitResult<Stats> {
     let now = Local::now();
     if end_date.and_hms(0, 0, 0) > now.naive_local() {
@@ -41,15 +43,15 @@ impl StatsService {
 
     let day_futs = days
       .into_iter()
-      .map(|(y, m, d)| self.get_day_unchecked(y, m, d))
+      .map(|(y, m, d)| self.get_day_unchecked(y, m, d, location_id))
       .collect::<Vec<_>>();
     let month_futs = months
       .into_iter()
-      .map(|(y, m)| self.get_month_unchecked(y, m))
+      .map(|(y, m)| self.get_month_unchecked(y, m, location_id))
       .collect::<Vec<_>>();
     let year_futs = years
       .into_iter()
-      .map(|y| self.get_year_unchecked(y))
+      .map(|y| self.get_year_unchecked(y, location_id))
       .collect::<Vec<_>>();
 
     let (day_stats, month_stats, year_stats) = tokio::join!(
@@ -80,12 +82,49 @@ impl StatsService {
     Ok(stats)
   }
 
-  async fn get_day_unchecked(&self, year: i32, month: u32, day: u32) -> HubbitResult<Stats> {
+  /// Drops the cached stats of every day, month and year touching the range,
+  /// both for the location and for all locations combined
+  pub async fn invalidate_range(
+    &self,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    location_id: Uuid,
+  ) -> HubbitResult<()> {
+    let mut keys = Vec::new();
+    let mut date = start_date;
+    while date <= end_date {
+      for &location_id in &[None, Some(location_id)] {
+        keys.push(cache_key(
+          format!("day:({},{},{})", date.year(), date.month(), date.day()),
+          location_id,
+        ));
+        keys.push(cache_key(
+          format!("month:({},{})", date.year(), date.month()),
+          location_id,
+        ));
+        keys.push(cache_key(format!("year:{}", date.year()), location_id));
+      }
+
+      date += Duration::days(1);
+    }
+
+    keys.sort_unstable();
+    keys.dedup();
+    redis_del(self.redis_pool.clone(), &keys).await
+  }
+
+  async fn get_day_unchecked(
+    &self,
+    year: i32,
+    month: u32,
+    day: u32,
+    location_id: Option<Uuid>,
+  ) -> HubbitResult<Stats> {
     let now = Local::now();
     let requested_date = Local.ymd(year, month, day);
 
     // Only check redis if not current day
-    let key = format!("day:({},{},{})", year, month, day);
+    let key = cache_key(format!("day:({},{},{})", year, month, day), location_id);
     if requested_date != now.date() {
       if let Ok(stats) = redis_get::<Stats>(self.redis_pool.clone(), &key).await {
         return Ok(stats);
@@ -93,7 +132,9 @@ impl StatsService {
     }
 
     let (start_time, end_time) = day_time_bounds(year, month, day);
-    let stats = self.get_range_fresh(start_time, end_time).await?;
+    let stats = self
+      .get_range_fresh(start_time, end_time, location_id)
+      .await?;
 
     // Only save to redis if current day
     if requested_date != now.date() {
@@ -105,15 +146,22 @@ impl StatsService {
     Ok(stats)
   }
 
-  async fn get_month_unchecked(&self, year: i32, month: u32) -> HubbitResult<Stats> {
+  async fn get_month_unchecked(
+    &self,
+    year: i32,
+    month: u32,
+    location_id: Option<Uuid>,
+  ) -> HubbitResult<Stats> {
     // If month is current month, work with partial month cache, which is a bit more complicated
-    let key = format!("month:({},{})", year, month);
+    let key = cache_key(format!("month:({},{})", year, month), location_id);
     if let Ok(stats) = redis_get(self.redis_pool.clone(), &key).await {
       return Ok(stats);
     }
 
     let (start_time, end_time) = month_time_bounds(year, month);
-    let stats = self.get_range_fresh(start_time, end_time).await?;
+    let stats = self
+      .get_range_fresh(start_time, end_time, location_id)
+      .await?;
     let stats_clone = stats.clone();
     let redis_pool = self.redis_pool.clone();
     tokio::spawn(async move { redis_set(redis_pool, key, stats_clone).await });
@@ -121,15 +169,17 @@ impl StatsService {
     Ok(stats)
   }
 
-  async fn get_year_unchecked(&self, year: i32) -> HubbitResult<Stats> {
+  async fn get_year_unchecked(&self, year: i32, location_id: Option<Uuid>) -> HubbitResult<Stats> {
     // If month is current year, work with partial year cache, which is a bit more complicated
-    let key = format!("year:{}", year);
+    let key = cache_key(format!("year:{}", year), location_id);
     if let Ok(stats) = redis_get(self.redis_pool.clone(), &key).await {
       return Ok(stats);
     }
 
     let (start_time, end_time) = year_time_bounds(year);
-    let stats = self.get_range_fresh(start_time, end_time).await?;
+    let stats = self
+      .get_range_fresh(start_time, end_time, location_id)
+      .await?;
     let stats_clone = stats.clone();
     let redis_pool = self.redis_pool.clone();
     tokio::spawn(async move { redis_set(redis_pool, key, stats_clone).await });
@@ -138,6 +188,18 @@ impl StatsService {
   }
 }
 
+// Bumped whenever the way stats are calculated changes, so that stale cached
+// stats are ignored
+const CACHE_VERSION: u32 = 2;
+
+// Stats for a single location are cached separately from the combined stats
+fn cache_key(key: String, location_id: Option<Uuid>) -> String {
+  match location_id {
+    Some(location_id) => format!("v{}:location:{}:{}", CACHE_VERSION, location_id, key),
+    None => format!("v{}:{}", CACHE_VERSION, key),
+  }
+}
+
 fn last_day_of_month(year: i32, month: u32) -> u32 {
   let first_day_of_next_month = if month == 12 {
     Local.ymd(year + 1, 1, 1).and_hms(0, 0, 0)
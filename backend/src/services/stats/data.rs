@@ -1,4 +1,5 @@
 use chrono::{DateTime, Datelike, Local, NaiveDate};
+use uuid::Uuid;
 
 use crate::{
   error::HubbitResult,
@@ -16,10 +17,11 @@ impl StatsService {
     &self,
     start_time: DateTime<Local>,
     end_time: DateTime<Local>,
+    location_id: Option<Uuid>,
   ) -> HubbitResult<Stats> {
     let sessions = self
       .user_session_repo
-      .get_range(start_time, end_time)
+      .get_range(start_time, end_time, location_id)
       .await?;
     let sessions = map_sessions(sessions);
     Ok(calculate_stats(&sessions, start_time, end_time))
@@ -37,7 +39,7 @@ impl StatsService {
 
     let sessions = self
       .user_session_repo
-      .get_range(*MIN_DATETIME, *MAX_DATETIME)
+      .get_range(*MIN_DATETIME, *MAX_DATETIME, None)
       .await?;
     let earliest_date = sessions
       .iter()
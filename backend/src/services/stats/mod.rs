@@ -65,42 +65,63 @@ impl StatsService {
     }
   }
 
-  pub async fn get_alltime(&self) -> HubbitResult<Stats> {
+  pub async fn get_alltime(&self, location_id: Option<Uuid>) -> HubbitResult<Stats> {
     let now = Local::now();
     let start_date = self.get_earliest_date().await?;
     let end_date = now.date().naive_local();
-    self.get_range(start_date, end_date).await
+    self.get_range(start_date, end_date, location_id).await
   }
 
-  pub async fn get_study_year(&self, year: i32) -> HubbitResult<Stats> {
+  pub async fn get_study_year(&self, year: i32, location_id: Option<Uuid>) -> HubbitResult<Stats> {
     let study_year = self.study_year_repo.get_by_year(year).await?;
     self
-      .get_range(study_year.start_date, study_year.end_date)
+      .get_range(study_year.start_date, study_year.end_date, location_id)
       .await
   }
 
-  pub async fn get_study_period(&self, year: i32, period: Period) -> HubbitResult<Stats> {
+  pub async fn get_study_period(
+    &self,
+    year: i32,
+    period: Period,
+    location_id: Option<Uuid>,
+  ) -> HubbitResult<Stats> {
     let study_period = self
       .study_period_repo
       .get_by_year_and_period(year, period)
       .await?;
     self
-      .get_range(study_period.start_date, study_period.end_date)
+      .get_range(study_period.start_date, study_period.end_date, location_id)
       .await
   }
 
-  pub async fn get_month(&self, year: i32, month: u32) -> HubbitResult<Stats> {
+  pub async fn get_month(
+    &self,
+    year: i32,
+    month: u32,
+    location_id: Option<Uuid>,
+  ) -> HubbitResult<Stats> {
     let (start_date, end_date) = month_date_bounds(year, month);
-    self.get_range(start_date, end_date).await
+    self.get_range(start_date, end_date, location_id).await
   }
 
-  pub async fn get_week(&self, year: i32, week: u32) -> HubbitResult<Stats> {
+  pub async fn get_week(
+    &self,
+    year: i32,
+    week: u32,
+    location_id: Option<Uuid>,
+  ) -> HubbitResult<Stats> {
     let (start_date, end_date) = week_date_bounds(year, week);
-    self.get_range(start_date, end_date).await
+    self.get_range(start_date, end_date, location_id).await
   }
 
-  pub async fn get_day(&self, year: i32, month: u32, day: u32) -> HubbitResult<Stats> {
+  pub async fn get_day(
+    &self,
+    year: i32,
+    month: u32,
+    day: u32,
+    location_id: Option<Uuid>,
+  ) -> HubbitResult<Stats> {
     let (start_date, end_date) = day_date_bounds(year, month, day);
-    self.get_range(start_date, end_date).await
+    self.get_range(start_date, end_date, location_id).await
   }
 }
@@ -14,13 +14,34 @@ pub fn map_sessions(sessions: Vec<UserSession>) -> HashMap<Uuid, Vec<DateTimeRan
       .or_insert_with(Vec::new)
       .push((
         session.start_time.with_timezone(&Local),
-        session.end_time.with_timezone(&Local),
+        session.effective_end_time().with_timezone(&Local),
       ));
   }
 
+  // A user can be present in several locations at once, which must not be
+  // counted twice when stats span all locations
+  for sessions in sessions_map.values_mut() {
+    *sessions = merge_overlapping(std::mem::take(sessions));
+  }
+
   sessions_map
 }
 
+fn merge_overlapping(mut sessions: Vec<DateTimeRange>) -> Vec<DateTimeRange> {
+  sessions.sort_by_key(|&(start_time, _)| start_time);
+  let mut merged: Vec<DateTimeRange> = Vec::with_capacity(sessions.len());
+  for (start_time, end_time) in sessions {
+    match merged.last_mut() {
+      Some((_, prev_end_time)) if start_time <= *prev_end_time => {
+        *prev_end_time = (*prev_end_time).max(end_time);
+      }
+      _ => merged.push((start_time, end_time)),
+    }
+  }
+
+  merged
+}
+
 pub fn calculate_stats(
   user_sessions: &HashMap<Uuid, Vec<DateTimeRange>>,
   range_start_time: DateTime<Local>,
@@ -34,15 +55,9 @@ pub fn calculate_stats(
           .iter()
           .fold(Duration::zero(), |prev, &(start_time, end_time)| {
             // Don't count session time outside of the range
-            let session_minutes = if end_time > range_end_time {
-              range_end_time - start_time
-            } else if start_time < range_start_time {
-              end_time - range_start_time
-            } else {
-              end_time - start_time
-            };
-
-            prev + session_minutes
+            let session_minutes = end_time.min(range_end_time) - start_time.max(range_start_time);
+
+            prev + session_minutes.max(Duration::zero())
           });
 
       (
@@ -74,7 +89,7 @@ pub fn year_time_bounds(year: i32) -> (DateTime<Local>, DateTime<Local>) {
 }
 
 pub fn month_time_bounds(year: i32, month: u32) -> (DateTime<Local>, DateTime<Local>) {
-  let start_time = Local.ymd(year, month as u32, 1).and_hms(0, 0, 0);
+  let start_time = Local.ymd(year, month, 1).and_hms(0, 0, 0);
   let end_time = if month == 12 {
     Local.ymd(year + 1, 1, 1).and_hms(23, 59, 59)
   } else {
@@ -90,11 +105,11 @@ pub fn day_time_bounds(year: i32, month: u32, day: u32) -> (DateTime<Local>, Dat
 }
 
 pub fn month_date_bounds(year: i32, month: u32) -> (NaiveDate, NaiveDate) {
-  let start_time = Local.ymd(year, month as u32, 1).naive_local();
+  let start_time = Local.ymd(year, month, 1).naive_local();
   let end_time = if month == 12 {
     Local.ymd(year + 1, 1, 1).and_hms(23, 59, 59)
   } else {
-    Local.ymd(year, month as u32 + 1, 1).and_hms(0, 0, 0)
+    Local.ymd(year, month + 1, 1).and_hms(0, 0, 0)
   } - Duration::seconds(1);
   let end_time = end_time.date().naive_local();
   (start_time, end_time)
@@ -108,6 +123,6 @@ pub fn week_date_bounds(year: i32, week: u32) -> (NaiveDate, NaiveDate) {
 
 pub fn day_date_bounds(year: i32, month: u32, day: u32) -> (NaiveDate, NaiveDate) {
   let start_time = Local.ymd(year, month, day).naive_local();
-  let end_time = Local.ymd(year, month as u32, day as u32).naive_local();
+  let end_time = Local.ymd(year, month, day).naive_local();
   (start_time, end_time)
 }
@@ -8,8 +8,8 @@ use uuid::Uuid;
 
 use crate::{
   error::HubbitResult,
+  identity::DynIdentityProvider,
   models::GammaUser,
-  repositories::user::UserRepository,
   services::util::{redis_get, redis_mget, redis_set},
   RedisPool,
 };
@@ -23,21 +23,21 @@ pub struct UserEntry {
 }
 
 pub struct UserService {
-  user_repo: UserRepository,
+  identity_provider: DynIdentityProvider,
   redis_pool: RedisPool,
   local_cache: Mutex<HashMap<Uuid, Arc<Mutex<Option<GammaUser>>>>>,
 }
 
 impl Clone for UserService {
   fn clone(&self) -> Self {
-    Self::new(self.user_repo.clone(), self.redis_pool.clone())
+    Self::new(self.identity_provider.clone(), self.redis_pool.clone())
   }
 }
 
 impl UserService {
-  pub fn new(user_repo: UserRepository, redis_pool: RedisPool) -> Self {
+  pub fn new(identity_provider: DynIdentityProvider, redis_pool: RedisPool) -> Self {
     Self {
-      user_repo,
+      identity_provider,
       redis_pool,
       local_cache: Mutex::new(HashMap::new()),
     }
@@ -64,9 +64,9 @@ impl UserService {
         // return old data and spawn task in background to fetch new data
         self.store_user_in_cache(user_entry.user.clone()).await;
         let redis_pool = self.redis_pool.clone();
-        let user_repo = self.user_repo.clone();
+        let identity_provider = self.identity_provider.clone();
         tokio::spawn(async move {
-          Self::fetch_and_store_user_redis(user_repo, redis_pool, id.to_string()).await
+          Self::fetch_and_store_user_redis(identity_provider, redis_pool, id.to_string()).await
         });
 
         return Ok(user_entry.user);
@@ -74,7 +74,7 @@ impl UserService {
     }
 
     // If in neither local cache or redis, fetch the user
-    Ok(self.fetch_and_store_user(id.to_string()).await?)
+    self.fetch_and_store_user(id.to_string()).await
   }
 
   pub async fn get_by_cid(&self, cid: String) -> HubbitResult<GammaUser> {
@@ -119,9 +119,9 @@ impl UserService {
             let id = user_entry.user.id;
             users.push(user_entry.user.clone());
             let redis_pool = self.redis_pool.clone();
-            let user_repo = self.user_repo.clone();
+            let identity_provider = self.identity_provider.clone();
             tokio::spawn(async move {
-              Self::fetch_and_store_user_redis(user_repo, redis_pool, id.to_string()).await
+              Self::fetch_and_store_user_redis(identity_provider, redis_pool, id.to_string()).await
             });
           }
         } else {
@@ -133,10 +133,10 @@ impl UserService {
     let futs = non_redis_cached_ids
       .iter()
       .map(|id| {
-        let user_repo = self.user_repo.clone();
+        let identity_provider = self.identity_provider.clone();
         let redis_pool = self.redis_pool.clone();
         async move {
-          match user_repo.get(id.to_string()).await {
+          match identity_provider.get_user(&id.to_string()).await {
             Ok(user) => {
               let user_entry = UserEntry {
                 user: user.clone(),
@@ -165,17 +165,18 @@ impl UserService {
 
   async fn fetch_and_store_user(&self, id: String) -> HubbitResult<GammaUser> {
     let user =
-      Self::fetch_and_store_user_redis(self.user_repo.clone(), self.redis_pool.clone(), id).await?;
+      Self::fetch_and_store_user_redis(self.identity_provider.clone(), self.redis_pool.clone(), id)
+        .await?;
     self.store_user_in_cache(user.clone()).await;
     Ok(user)
   }
 
   async fn fetch_and_store_user_redis(
-    user_repo: UserRepository,
+    identity_provider: DynIdentityProvider,
     redis_pool: RedisPool,
     id: String,
   ) -> HubbitResult<GammaUser> {
-    let user = user_repo.get(id).await?;
+    let user = identity_provider.get_user(&id).await?;
     let user_entry = UserEntry {
       user: user.clone(),
       updated_at: Local::now(),
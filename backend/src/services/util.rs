@@ -21,21 +21,88 @@ where
   Ok(())
 }
 
+pub async fn redis_del(redis_pool: RedisPool, keys: &[String]) -> HubbitResult<()> {
+  let mut redis_conn = redis_pool.get().await?;
+  redis_conn.del::<&[String], ()>(keys).await?;
+  Ok(())
+}
+
 pub async fn redis_mget<T: DeserializeOwned>(
   redis_pool: RedisPool,
   keys: &[String],
 ) -> HubbitResult<Vec<Option<T>>> {
   let mut redis_conn = redis_pool.get().await?;
   let raw_result: Vec<Option<String>> = redis_conn.get(keys).await?;
-  Ok(
-    raw_result
-      .into_iter()
-      .map(|raw| -> HubbitResult<Option<T>> {
-        match raw {
-          Some(raw) => Ok(Some(serde_json::from_str::<T>(&raw)?)),
-          None => Ok(None),
-        }
-      })
-      .collect::<HubbitResult<Vec<Option<T>>>>()?,
-  )
+  raw_result
+    .into_iter()
+    .map(|raw| -> HubbitResult<Option<T>> {
+      match raw {
+        Some(raw) => Ok(Some(serde_json::from_str::<T>(&raw)?)),
+        None => Ok(None),
+      }
+    })
+    .collect::<HubbitResult<Vec<Option<T>>>>()
+}
+
+pub async fn redis_set_ex<T>(
+  redis_pool: RedisPool,
+  key: String,
+  value: T,
+  seconds: usize,
+) -> HubbitResult<()>
+where
+  T: Serialize,
+{
+  let mut redis_conn = redis_pool.get().await?;
+  redis_conn
+    .set_ex::<String, String, String>(key, serde_json::to_string(&value)?, seconds)
+    .await?;
+  Ok(())
+}
+
+/// Adds the member to the set, and keeps the set for at least `seconds`. The
+/// expiry is only ever extended, so that the set outlives every member.
+pub async fn redis_sadd(
+  redis_pool: RedisPool,
+  key: String,
+  member: String,
+  seconds: usize,
+) -> HubbitResult<()> {
+  let mut redis_conn = redis_pool.get().await?;
+  redis_conn.sadd::<&str, String, ()>(&key, member).await?;
+  // Negative if the set has no expiry yet
+  let ttl: i64 = redis_conn.ttl(&key).await?;
+  if ttl < seconds as i64 {
+    redis_conn.expire::<&str, ()>(&key, seconds).await?;
+  }
+  Ok(())
+}
+
+pub async fn redis_srem(redis_pool: RedisPool, key: String, member: String) -> HubbitResult<()> {
+  let mut redis_conn = redis_pool.get().await?;
+  redis_conn.srem::<String, String, ()>(key, member).await?;
+  Ok(())
+}
+
+pub async fn redis_smembers(redis_pool: RedisPool, key: &str) -> HubbitResult<Vec<String>> {
+  let mut redis_conn = redis_pool.get().await?;
+  Ok(redis_conn.smembers(key).await?)
+}
+
+/// Sets the key unless it already exists, returning whether it was set
+pub async fn redis_set_nx_ex(
+  redis_pool: RedisPool,
+  key: String,
+  seconds: usize,
+) -> HubbitResult<bool> {
+  let mut redis_conn = redis_pool.get().await?;
+  let result: Option<String> = mobc_redis::redis::cmd("SET")
+    .arg(key)
+    .arg(1)
+    .arg("NX")
+    .arg("EX")
+    .arg(seconds)
+    .query_async(&mut *redis_conn)
+    .await?;
+  Ok(result.is_some())
 }
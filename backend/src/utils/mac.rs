@@ -0,0 +1,108 @@
+/// Normalizes a MAC address written with colons or dashes
+/// (`aa:bb:cc:dd:ee:ff`), in Cisco notation (`aabb.ccdd.eeff`) or as bare hex
+/// (`aabbccddeeff`) into the uppercase colon separated form that is stored.
+/// Returns `None` if the address is not written in any of these notations.
+pub fn normalize_mac_addr(raw_mac_addr: &str) -> Option<String> {
+  let raw_mac_addr = raw_mac_addr.trim();
+  let digits = match raw_mac_addr.len() {
+    17 => {
+      let separator = raw_mac_addr.chars().nth(2)?;
+      if separator != ':' && separator != '-' {
+        return None;
+      }
+      strip_separators(raw_mac_addr, separator, 3)?
+    }
+    14 => strip_separators(raw_mac_addr, '.', 5)?,
+    12 => raw_mac_addr.to_owned(),
+    _ => return None,
+  };
+
+  if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+    return None;
+  }
+
+  let digits = digits.to_uppercase();
+  let octets = (0..6)
+    .map(|i| &digits[i * 2..i * 2 + 2])
+    .collect::<Vec<_>>();
+  Some(octets.join(":"))
+}
+
+/// Whether the locally administered bit is set, which phones set for the
+/// randomized addresses that they rotate between networks or over time
+pub fn is_randomized_mac_addr(mac_addr: &str) -> bool {
+  mac_addr
+    .get(..2)
+    .and_then(|first_octet| u8::from_str_radix(first_octet, 16).ok())
+    .map_or(false, |first_octet| first_octet & 0b10 != 0)
+}
+
+// Removes a separator that is expected at every `group_len`th position
+fn strip_separators(raw_mac_addr: &str, separator: char, group_len: usize) -> Option<String> {
+  let mut digits = String::with_capacity(12);
+  for (i, c) in raw_mac_addr.chars().enumerate() {
+    if i % group_len == group_len - 1 {
+      if c != separator {
+        return None;
+      }
+    } else {
+      digits.push(c);
+    }
+  }
+  Some(digits)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn normalizes_every_notation() {
+    let normalized = Some("AA:BB:CC:DD:EE:0F".to_owned());
+    assert_eq!(normalize_mac_addr("aa:bb:cc:dd:ee:0f"), normalized);
+    assert_eq!(normalize_mac_addr("AA-BB-CC-DD-EE-0F"), normalized);
+    assert_eq!(normalize_mac_addr("aabb.ccdd.ee0f"), normalized);
+    assert_eq!(normalize_mac_addr("aabbccddee0f"), normalized);
+    assert_eq!(normalize_mac_addr("  aa:bb:cc:dd:ee:0f\n"), normalized);
+  }
+
+  #[test]
+  fn rejects_mixed_or_misplaced_separators() {
+    assert_eq!(normalize_mac_addr("aa:bb-cc:dd:ee:ff"), None);
+    assert_eq!(normalize_mac_addr("aa-bb-cc-dd-ee:ff"), None);
+    assert_eq!(normalize_mac_addr("aabb.ccdd:eeff"), None);
+    assert_eq!(normalize_mac_addr("aab:bcc:dde:eff"), None);
+    assert_eq!(normalize_mac_addr("aa.bb.cc.dd.ee.ff"), None);
+  }
+
+  #[test]
+  fn rejects_wrong_lengths_and_non_hex_digits() {
+    assert_eq!(normalize_mac_addr(""), None);
+    assert_eq!(normalize_mac_addr("aa:bb:cc:dd:ee"), None);
+    assert_eq!(normalize_mac_addr("aabbccddeeff00"), None);
+    assert_eq!(normalize_mac_addr("gg:bb:cc:dd:ee:ff"), None);
+    assert_eq!(normalize_mac_addr("+abbccddeeff"), None);
+  }
+
+  #[test]
+  fn rejects_non_ascii_of_the_right_byte_length() {
+    // Each of these is as many bytes long as one of the notations
+    assert_eq!(normalize_mac_addr("aa:bb:cc:dd:ee:é"), None);
+    assert_eq!(normalize_mac_addr("aaébb:cc:dd:ee:f"), None);
+    assert_eq!(normalize_mac_addr("aabb.ccdd.eeé"), None);
+    assert_eq!(normalize_mac_addr("aabbccddeeé"), None);
+    assert_eq!(normalize_mac_addr("aa:bb:cc:dd:€:f"), None);
+    assert_eq!(normalize_mac_addr("a€bbccddef"), None);
+  }
+
+  #[test]
+  fn detects_the_locally_administered_bit() {
+    assert!(is_randomized_mac_addr("02:00:00:00:00:00"));
+    assert!(is_randomized_mac_addr("DA:A1:19:00:00:00"));
+    assert!(is_randomized_mac_addr("FE:FF:FF:FF:FF:FF"));
+    assert!(!is_randomized_mac_addr("00:1A:2B:3C:4D:5E"));
+    assert!(!is_randomized_mac_addr("FD:FF:FF:FF:FF:FF"));
+    assert!(!is_randomized_mac_addr("é:00:00:00:00:00"));
+    assert!(!is_randomized_mac_addr(""));
+  }
+}
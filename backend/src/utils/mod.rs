@@ -1,4 +1,6 @@
-pub mod gamma;
+pub mod mac;
+pub mod oui;
+pub mod token;
 
 use chrono::{DateTime, Local, TimeZone};
 use lazy_static::lazy_static;
@@ -0,0 +1,115 @@
+use std::{collections::HashMap, fs};
+
+use crate::error::HubbitResult;
+
+static BUNDLED_OUI_CSV: &str = include_str!("../../data/oui.csv");
+
+// Words that are dropped from the end of vendor names when suggesting names
+static COMPANY_SUFFIXES: &[&str] = &[
+  "ab",
+  "ag",
+  "co",
+  "co.",
+  "communications",
+  "computer",
+  "corp",
+  "corp.",
+  "corporate",
+  "corporation",
+  "electronics",
+  "foundation",
+  "gmbh",
+  "inc",
+  "inc.",
+  "llc",
+  "ltd",
+  "ltd.",
+  "networks",
+  "semiconductor",
+  "systems",
+  "tech",
+  "technologies",
+  "technology",
+  "trading",
+];
+
+/// Maps the first three octets of MAC addresses to the vendor they are
+/// assigned to, read from the IEEE MA-L registry in CSV format
+#[derive(Debug)]
+pub struct OuiTable {
+  vendors: HashMap<String, String>,
+}
+
+impl OuiTable {
+  /// The table compiled into the binary, a sample of the registry unless
+  /// `scripts/update-oui.sh` has been run
+  pub fn bundled() -> Self {
+    Self::parse(BUNDLED_OUI_CSV)
+  }
+
+  pub fn from_file(path: &str) -> HubbitResult<Self> {
+    Ok(Self::parse(&fs::read_to_string(path)?))
+  }
+
+  fn parse(csv: &str) -> Self {
+    let vendors = csv
+      .lines()
+      .skip(1)
+      .filter_map(|line| {
+        let fields = split_csv_line(line);
+        let assignment = fields.get(1)?.to_uppercase();
+        let name = fields.get(2)?.trim();
+        if assignment.len() != 6 || name.is_empty() {
+          return None;
+        }
+        Some((assignment, name.to_owned()))
+      })
+      .collect();
+    Self { vendors }
+  }
+
+  /// The vendor of a normalized address, `None` for randomized addresses as
+  /// they aren't assigned to anyone
+  pub fn vendor(&self, mac_addr: &str) -> Option<&str> {
+    if super::mac::is_randomized_mac_addr(mac_addr) {
+      return None;
+    }
+
+    let assignment = mac_addr.get(..8)?.replace(':', "");
+    self.vendors.get(&assignment).map(String::as_str)
+  }
+
+  /// A name for a device of the address' vendor, e.g. "Apple device"
+  pub fn suggested_name(&self, mac_addr: &str) -> Option<String> {
+    let vendor = self.vendor(mac_addr)?;
+    let vendor = vendor.split(&[',', '('][..]).next().unwrap_or(vendor);
+    let mut words = vendor.split_whitespace().collect::<Vec<_>>();
+    while words.len() > 1
+      && COMPANY_SUFFIXES.contains(&words[words.len() - 1].to_lowercase().as_str())
+    {
+      words.pop();
+    }
+    Some(format!("{} device", words.join(" ")))
+  }
+}
+
+// Splits a line of CSV, where fields may be quoted to contain commas
+fn split_csv_line(line: &str) -> Vec<String> {
+  let mut fields = Vec::new();
+  let mut field = String::new();
+  let mut in_quotes = false;
+  let mut chars = line.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '"' if in_quotes && chars.peek() == Some(&'"') => {
+        field.push('"');
+        chars.next();
+      }
+      '"' => in_quotes = !in_quotes,
+      ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+      c => field.push(c),
+    }
+  }
+  fields.push(field);
+  fields
+}
@@ -0,0 +1,21 @@
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+
+/// Tells personal access tokens apart from API keys, and makes them easy to
+/// spot when leaked
+pub const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "hubbit_pat_";
+
+/// A random alphanumeric token, long enough to not be guessable
+pub fn generate_token() -> String {
+  thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(32)
+    .map(char::from)
+    .collect()
+}
+
+/// Tokens are random and long, so a fast hash is enough to keep them from being
+/// usable by anyone reading where they are stored
+pub fn hash_token(token: &str) -> String {
+  hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -4,7 +4,7 @@ import { faTrashAlt } from '@fortawesome/free-regular-svg-icons';
 import { FontAwesomeIcon } from '@fortawesome/react-fontawesome';
 import { gql } from '@urql/core';
 
-import { DeviceFragment, useSetDevicesMutation } from '../../__generated__/graphql';
+import { DeviceFragment, DeviceWarning, DeviceWarningKind, useSetDevicesMutation } from '../../__generated__/graphql';
 
 import styles from './DeviceList.module.scss';
 
@@ -20,7 +20,13 @@ export const DEVICE_FRAGMENT = gql`
 gql`
   mutation SetDevices($input: SetDevicesInput!) {
     setDevices(data: $input) {
-      ...Device
+      devices {
+        ...Device
+      }
+      warnings {
+        address
+        kind
+      }
     }
   }
 
@@ -43,6 +49,7 @@ interface EditableDevice {
 
 const DeviceList = ({ initialDevices }: Props) => {
   const [devices, setDevices] = useState(initState(initialDevices));
+  const [warnings, setWarnings] = useState<DeviceWarning[]>([]);
 
   const [, updateDevices] = useSetDevicesMutation();
 
@@ -110,6 +117,11 @@ const DeviceList = ({ initialDevices }: Props) => {
           ))}
         </tbody>
       </table>
+      {warnings.map(warning => (
+        <div key={warning.address} className={styles.warningText}>
+          {warningText(warning)}
+        </div>
+      ))}
       <div className={styles.helpText}>
         Don&apos;t know how to find your <a href={'https://en.wikipedia.org/wiki/MAC_address'}>MAC Address</a>? Take a
         look at <a href={'https://www.wikihow.com/Find-the-MAC-Address-of-Your-Computer'}>this guide</a>!
@@ -147,7 +159,8 @@ const DeviceList = ({ initialDevices }: Props) => {
           }).then(({ data }) => {
             // TODO(rasmus): if error show it to user
             if (data) {
-              setDevices(initState(data.setDevices));
+              setDevices(initState(data.setDevices.devices));
+              setWarnings(data.setDevices.warnings);
             }
           });
         }}
@@ -158,6 +171,13 @@ const DeviceList = ({ initialDevices }: Props) => {
   );
 };
 
+function warningText(warning: DeviceWarning): string {
+  switch (warning.kind) {
+    case DeviceWarningKind.RandomizedAddress:
+      return `${warning.address} is randomized by the device, it will likely change and the device stop being tracked. Turn off private addresses for this network on the device.`;
+  }
+}
+
 function hasUnsavedChanges(device: EditableDevice): boolean {
   return device.isNew || device.address !== device.savedAddress || device.description !== device.savedDescription;
 }
@@ -44,7 +44,7 @@ export const parseGqlError = (error: CombinedError): GqlError => {
 };
 
 export const authRedirect = (path: string): Redirect => ({
-  destination: `/api/auth/gamma/login?from=${path}`,
+  destination: `/api/auth/login?from=${path}`,
   permanent: false,
 });
 